@@ -0,0 +1,236 @@
+//! Anomalous hours in the hourly sentiment and volume series
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+use chrono::{Duration, NaiveDateTime};
+use crate::{format_hour_range, hour_key, print_ranking, write_ranking, EARLIEST_PLAUSIBLE_HOUR};
+
+#[derive(Debug, Clone)]
+pub(crate) struct HourAnomaly {
+    hour: String,
+    mean_sentiment: f64,
+    baseline_sentiment: f64,
+    sentiment_z: Option<f64>,
+    volume: usize,
+    baseline_volume: f64,
+    volume_z: Option<f64>,
+    score: f64,
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let mid = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[mid]
+    } else {
+        (values[mid - 1] + values[mid]) / 2.0
+    }
+}
+
+// Modified z-score of `value` against `baseline` using the median absolute deviation.
+// Falls back to the mean absolute deviation when more than half the baseline is identical.
+// For count data the scale never drops below the Poisson noise of the baseline median.
+fn robust_z_score(value: f64, baseline: &[f64], count_data: bool) -> Option<(f64, f64)> {
+    if baseline.is_empty() {
+        return None;
+    }
+    
+    let mut values = baseline.to_vec();
+    let center = median(&mut values);
+    let mut deviations: Vec<f64> = baseline.iter().map(|v| (v - center).abs()).collect();
+    let mad = median(&mut deviations);
+    
+    let mut scale = if mad > f64::EPSILON {
+        1.4826 * mad
+    } else {
+        let mean_ad = deviations.iter().sum::<f64>() / deviations.len() as f64;
+        1.2533 * mean_ad
+    };
+    if count_data {
+        scale = scale.max(center.sqrt()).max(1.0);
+    }
+    
+    if scale > f64::EPSILON {
+        Some(((value - center) / scale, center))
+    } else {
+        None
+    }
+}
+
+// Flag hours whose mean sentiment drops, or whose volume spikes, relative to the
+// preceding `window` hours. Hours without posts count as zero volume in the baseline.
+// `hours` are the sorted hours with posts, so gaps cost nothing however long they are.
+pub(crate) fn detect_hour_anomalies(
+    hour_sentiment: &HashMap<String, f64>,
+    hour_count: &HashMap<String, usize>,
+    hours: &[NaiveDateTime],
+    window: usize,
+    threshold: f64,
+) -> Vec<HourAnomaly> {
+    let first = match hours.first() {
+        Some(first) => *first,
+        None => return Vec::new(),
+    };
+    let mean_of = |key: &str| {
+        let count = hour_count.get(key).copied().unwrap_or(0);
+        let mean = if count > 0 {
+            hour_sentiment.get(key).map(|sum| sum / count as f64)
+        } else {
+            None
+        };
+        (count, mean)
+    };
+    
+    let mut anomalies = Vec::new();
+    // Hours in the first `window` hours of the series have no full baseline
+    for &hour in hours.iter().filter(|&&hour| hour >= first + Duration::hours(window as i64)) {
+        let key = hour_key(hour);
+        let (count, mean) = mean_of(&key);
+        let mean = match mean {
+            Some(mean) => mean,
+            None => continue,
+        };
+        
+        let previous: Vec<(usize, Option<f64>)> = (1..=window as i64)
+            .rev()
+            .map(|back| mean_of(&hour_key(hour - Duration::hours(back))))
+            .collect();
+        let volume_baseline: Vec<f64> = previous.iter().map(|(c, _)| *c as f64).collect();
+        let sentiment_baseline: Vec<f64> = previous.iter().filter_map(|(_, m)| *m).collect();
+        
+        let volume = robust_z_score(count as f64, &volume_baseline, true);
+        let sentiment = if sentiment_baseline.len() * 2 >= window {
+            robust_z_score(mean, &sentiment_baseline, false)
+        } else {
+            None
+        };
+        
+        let sentiment_z = sentiment.map(|(z, _)| z);
+        let volume_z = volume.map(|(z, _)| z);
+        let drop_score = sentiment_z.map(|z| -z).unwrap_or(0.0);
+        let spike_score = volume_z.unwrap_or(0.0);
+        let score = drop_score.max(spike_score);
+        
+        if score >= threshold {
+            anomalies.push(HourAnomaly {
+                hour: key,
+                mean_sentiment: mean,
+                baseline_sentiment: sentiment.map(|(_, center)| center).unwrap_or(mean),
+                sentiment_z,
+                volume: count,
+                baseline_volume: volume.map(|(_, center)| center).unwrap_or(count as f64),
+                volume_z,
+                score,
+            });
+        }
+    }
+    
+    anomalies.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    anomalies
+}
+
+fn format_z_score(z: Option<f64>) -> String {
+    match z {
+        Some(z) => format!("{:.2}", z),
+        None => "n/a".to_string(),
+    }
+}
+
+pub(crate) fn dump_hour_anomalies(anomalies: &[HourAnomaly], dropped_posts: usize, print_limit: usize, output_dir: &Path) {
+    let mut lines = Vec::new();
+    for (i, anomaly) in anomalies.iter().enumerate() {
+        let mut kinds = Vec::new();
+        if anomaly.sentiment_z.is_some_and(|z| -z >= anomaly.score) {
+            kinds.push("sentiment drop");
+        }
+        if anomaly.volume_z.is_some_and(|z| z >= anomaly.score) {
+            kinds.push("volume spike");
+        }
+        
+        lines.push(format!(
+            "{}. {} [{}] mean sentiment {:.4} (baseline {:.4}, z {}), {} posts (baseline {:.1}, z {})",
+            i + 1,
+            format_hour_range(&anomaly.hour),
+            kinds.join(", "),
+            anomaly.mean_sentiment,
+            anomaly.baseline_sentiment,
+            format_z_score(anomaly.sentiment_z),
+            anomaly.volume,
+            anomaly.baseline_volume,
+            format_z_score(anomaly.volume_z),
+        ));
+    }
+    
+    // The console shows the first `print_limit` hours, the file all of them
+    let mut printed: Vec<String> = lines.iter().take(print_limit).cloned().collect();
+    if anomalies.is_empty() {
+        printed.push("No anomalous hours found".to_string());
+    }
+    if dropped_posts > 0 {
+        let note = format!(
+            "{} posts dated before {}:00 or in the future were left out of the hourly series",
+            dropped_posts, EARLIEST_PLAUSIBLE_HOUR
+        );
+        printed.push(note.clone());
+        lines.push(note);
+    }
+    print_ranking("Anomalous Hours", &printed);
+    write_ranking("Anomalous Hours", "anomalous_hours.txt", &lines, output_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_hour_key, plausible_hours};
+    
+    // Hourly sums and counts from (hour offset from 2025-02-01 00:00, posts, mean sentiment)
+    fn hour_series(hours: &[(i64, usize, f64)]) -> (HashMap<String, f64>, HashMap<String, usize>) {
+        let start = parse_hour_key("2025-02-01 00").unwrap();
+        let mut sums = HashMap::new();
+        let mut counts = HashMap::new();
+        for &(offset, posts, mean) in hours {
+            let key = hour_key(start + Duration::hours(offset));
+            sums.insert(key.clone(), mean * posts as f64);
+            counts.insert(key, posts);
+        }
+        (sums, counts)
+    }
+    
+    #[test]
+    fn anomalies_flag_sentiment_drops_and_volume_spikes() {
+        let mut hours: Vec<(i64, usize, f64)> = (0..24).map(|h| (h, 2, if h % 2 == 0 { 0.1 } else { 0.2 })).collect();
+        hours.push((24, 2, -0.5));
+        hours.push((25, 30, 0.15));
+        let (sums, counts) = hour_series(&hours);
+        let (series, dropped) = plausible_hours(&counts);
+        assert_eq!((series.len(), dropped), (26, 0));
+        
+        let anomalies = detect_hour_anomalies(&sums, &counts, &series, 24, 3.0);
+        let flagged: Vec<_> = anomalies.iter().map(|anomaly| anomaly.hour.as_str()).collect();
+        assert_eq!(flagged.len(), 2, "{:?}", anomalies);
+        assert!(flagged.contains(&"2025-02-02 00"));
+        assert!(flagged.contains(&"2025-02-02 01"));
+        let drop = anomalies.iter().find(|anomaly| anomaly.hour == "2025-02-02 00").unwrap();
+        assert!(drop.sentiment_z.unwrap() < -3.0);
+        let spike = anomalies.iter().find(|anomaly| anomaly.hour == "2025-02-02 01").unwrap();
+        assert!(spike.volume_z.unwrap() > 3.0);
+    }
+    
+    #[test]
+    fn anomalies_skip_implausible_hours_and_bridge_gaps() {
+        // A year-long gap and posts dated 1970 and 2099 must not expand into a dense series
+        let (mut sums, mut counts) = hour_series(&[(0, 1, 0.5), (1, 1, 0.5), (2, 1, 0.5), (24 * 365, 5, 0.5)]);
+        for bogus in ["1970-01-01 00", "2099-12-31 23"] {
+            sums.insert(bogus.to_string(), 0.0);
+            counts.insert(bogus.to_string(), 3);
+        }
+        let (series, dropped) = plausible_hours(&counts);
+        assert_eq!((series.len(), dropped), (4, 6));
+        
+        let anomalies = detect_hour_anomalies(&sums, &counts, &series, 3, 2.0);
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].hour, "2026-02-01 00");
+        assert_eq!(anomalies[0].baseline_volume, 0.0);
+    }
+}
@@ -0,0 +1,83 @@
+//! Sentiment broken down by account and status category
+
+use std::collections::HashMap;
+use std::path::Path;
+use crate::{print_ranking, write_ranking, MastodonData, SentimentStats, UNKNOWN_VISIBILITY};
+
+// (dimension, value) pairs a status is counted under in the category breakdown
+pub(crate) fn status_categories(mastodon_data: &MastodonData) -> [(&'static str, &str); 4] {
+    let account = if mastodon_data.bot {
+        "bot"
+    } else if mastodon_data.group {
+        "group"
+    } else {
+        "person"
+    };
+    let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+    [
+        ("account", account),
+        ("visibility", mastodon_data.visibility.as_deref().unwrap_or(UNKNOWN_VISIBILITY)),
+        ("sensitive", yes_no(mastodon_data.sensitive)),
+        ("content warning", yes_no(mastodon_data.has_spoiler)),
+    ]
+}
+
+pub(crate) fn dump_category_sentiment(category_stats: &HashMap<String, HashMap<String, SentimentStats>>, output_dir: &Path) {
+    let mut dimensions: Vec<_> = category_stats.iter().collect();
+    dimensions.sort_by(|a, b| a.0.cmp(b.0));
+    
+    let mut lines = Vec::new();
+    for (dimension, values) in dimensions {
+        let mut values: Vec<_> = values.iter().collect();
+        values.sort_by(|a, b| b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(b.0)));
+        for (value, stats) in values {
+            lines.push(format!(
+                "{} = {} with {} posts, total sentiment {:+} and mean sentiment {:+.4}",
+                dimension, value, stats.count, stats.sum, stats.mean()
+            ));
+        }
+    }
+    print_ranking("Sentiment by Category", &lines);
+    write_ranking("Sentiment by Category", "category_sentiment.txt", &lines, output_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{aggregate, exported_status};
+    use crate::ProcessingOptions;
+    use serde_json::json;
+    use std::collections::HashSet;
+    
+    #[test]
+    fn category_filters_and_breakdown() {
+        let bot = json!({"id": "2", "username": "newsbot", "acct": "newsbot@example.org", "bot": true});
+        let lines = [
+            exported_status(json!({"sentiment": 0.5})),
+            exported_status(json!({"sentiment": -0.5, "account": bot})),
+            exported_status(json!({"sentiment": -0.25, "sensitive": true})),
+            exported_status(json!({"sentiment": 0.25, "spoilerText": "politics"})),
+            exported_status(json!({"sentiment": 0.75, "visibility": "UNLISTED"})),
+        ];
+        let options = ProcessingOptions {
+            exclude_bots: true,
+            exclude_sensitive: true,
+            visibilities: Some(HashSet::from(["public".to_string()])),
+            ..ProcessingOptions::default()
+        };
+        let (aggregates, _) = aggregate(&lines, &options);
+        assert_eq!(aggregates.category_filtered, 4);
+        assert_eq!(aggregates.language_stats["en"].count, 1);
+        
+        // Excluded statuses still appear in the breakdown
+        let category = |dimension: &str, value: &str| aggregates.category_stats[dimension][value].count;
+        assert_eq!(category("account", "bot"), 1);
+        assert_eq!(category("account", "person"), 4);
+        assert_eq!(category("visibility", "unlisted"), 1);
+        assert_eq!(category("sensitive", "yes"), 1);
+        assert_eq!(category("content warning", "yes"), 1);
+        
+        let (aggregates, _) = aggregate(&lines, &ProcessingOptions::default());
+        assert_eq!(aggregates.category_filtered, 0);
+        assert_eq!(aggregates.language_stats["en"].count, 5);
+    }
+}
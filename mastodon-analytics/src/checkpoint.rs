@@ -0,0 +1,379 @@
+//! Periodic per-rank checkpoints and `--resume`
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use memmap2::MmapOptions;
+use mpi::traits::Communicator;
+use serde::{Deserialize, Serialize};
+use serde_json::from_str;
+use crate::{merge_aggregates, next_line_start, Aggregates, PartitionedData};
+use crate::spill::SpillState;
+
+// A byte range a rank scans, with the last line it has handled. Like every scanned range it
+// holds the lines starting in (start, end], plus the line at offset 0 when start is 0.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub(crate) struct ScanSegment {
+    start: u64,
+    pub(crate) end: u64,
+    pub(crate) last_line: Option<u64>,
+}
+
+impl ScanSegment {
+    pub(crate) fn new(start: u64, end: u64) -> Self {
+        ScanSegment { start, end, last_line: None }
+    }
+    
+    // Where scanning continues; starting one byte into the last handled line skips past it
+    pub(crate) fn resume_start(&self) -> u64 {
+        self.last_line.map_or(self.start, |offset| offset + 1)
+    }
+    
+    // Whether the line starting at `offset` belongs to this segment
+    fn contains_line(&self, offset: u64) -> bool {
+        (offset > self.start || offset == 0) && offset <= self.end
+    }
+}
+
+// Lines handled between looks at the clock; reading it on every line shows up in the scan
+const CLOCK_CHECK_LINES: usize = 1024;
+
+// Identifies the input a checkpoint was taken from
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+struct InputFingerprint {
+    path: String,
+    len: u64,
+    modified: i64,
+}
+
+impl InputFingerprint {
+    fn of(path: &str) -> Self {
+        let metadata = fs::metadata(path).expect("Failed to get file metadata");
+        InputFingerprint { path: path.to_string(), len: metadata.size(), modified: metadata.mtime() }
+    }
+}
+
+// Everything a rank has built during its scan
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct ScanState {
+    pub(crate) aggregates: Aggregates,
+    pub(crate) partitioned: PartitionedData,
+    pub(crate) lines_processed: usize,
+}
+
+// A rank's scan: what is left to read, what has been built, and where it is persisted
+pub(crate) struct ScanProgress {
+    pub(crate) segments: Vec<ScanSegment>,
+    pub(crate) state: ScanState,
+    pub(crate) spill: Option<SpillState>,
+    pub(crate) checkpointer: Option<Checkpointer>,
+}
+
+// First line of a checkpoint file; the second line holds the rank's `ScanState`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct CheckpointMeta {
+    generation: u64,
+    rank: usize,
+    size: usize,
+    input: InputFingerprint,
+    segments: Vec<ScanSegment>,
+}
+
+// Writes one rank's checkpoints. Each run (or resumption) is a new generation; a generation is
+// consistent once every one of its ranks has written a file, since ranks share no state during the scan.
+pub(crate) struct Checkpointer {
+    dir: PathBuf,
+    generation: u64,
+    rank: usize,
+    size: usize,
+    input: InputFingerprint,
+    interval: f64,
+    last_write: Instant,
+    // Lines handled since the clock was last read
+    unchecked_lines: usize,
+}
+
+fn checkpoint_file_name(generation: u64, rank: usize) -> String {
+    format!("gen{:06}-rank{:05}.ckpt", generation, rank)
+}
+
+impl Checkpointer {
+    // Atomically replace this rank's checkpoint of the current generation
+    pub(crate) fn write(&mut self, segments: &[ScanSegment], state: &ScanState) {
+        let meta = CheckpointMeta {
+            generation: self.generation,
+            rank: self.rank,
+            size: self.size,
+            input: self.input.clone(),
+            segments: segments.to_vec(),
+        };
+        let path = self.dir.join(checkpoint_file_name(self.generation, self.rank));
+        let partial_path = path.with_extension("ckpt.partial");
+        let file = File::create(&partial_path).expect("Failed to create checkpoint file");
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &meta).expect("Failed to write checkpoint");
+        writeln!(writer).expect("Failed to write checkpoint");
+        serde_json::to_writer(&mut writer, state).expect("Failed to write checkpoint");
+        writeln!(writer).expect("Failed to write checkpoint");
+        writer.into_inner().expect("Failed to write checkpoint").sync_all().expect("Failed to sync checkpoint");
+        fs::rename(&partial_path, &path).expect("Failed to move checkpoint into place");
+        self.last_write = Instant::now();
+    }
+    
+    // Called once per handled line
+    pub(crate) fn write_if_due(&mut self, segments: &[ScanSegment], state: &ScanState) {
+        self.unchecked_lines += 1;
+        if self.unchecked_lines < CLOCK_CHECK_LINES {
+            return;
+        }
+        self.unchecked_lines = 0;
+        if self.last_write.elapsed().as_secs_f64() >= self.interval {
+            self.write(segments, state);
+        }
+    }
+}
+
+// Checkpoint files in `dir` as (generation, path) pairs
+fn checkpoint_files(dir: &Path) -> Vec<(u64, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        let name = path.file_name()?.to_str()?;
+        let generation = name.strip_prefix("gen")?.strip_suffix(".ckpt")?.split('-').next()?.parse().ok()?;
+        Some((generation, path))
+    })
+    .collect()
+}
+
+fn read_checkpoint_meta(path: &Path) -> Option<CheckpointMeta> {
+    let file = File::open(path).ok()?;
+    let mut line = String::new();
+    io::BufRead::read_line(&mut io::BufReader::new(file), &mut line).ok()?;
+    from_str(&line).ok()
+}
+
+fn read_checkpoint_state(dir: &Path, meta: &CheckpointMeta) -> ScanState {
+    let path = dir.join(checkpoint_file_name(meta.generation, meta.rank));
+    let file = File::open(&path).unwrap_or_else(|_| panic!("Failed to open checkpoint {}", path.display()));
+    let line = io::BufRead::lines(io::BufReader::new(file))
+        .nth(1)
+        .and_then(Result::ok)
+        .unwrap_or_else(|| panic!("Checkpoint {} has no state", path.display()));
+    from_str(&line).unwrap_or_else(|error| panic!("Failed to parse checkpoint {}: {}", path.display(), error))
+}
+
+// Metadata of every rank of the newest generation that all of its ranks completed
+fn latest_consistent_checkpoint(dir: &Path) -> Option<Vec<CheckpointMeta>> {
+    let mut generations: HashMap<u64, Vec<CheckpointMeta>> = HashMap::new();
+    for (generation, path) in checkpoint_files(dir) {
+        if let Some(meta) = read_checkpoint_meta(&path) {
+            generations.entry(generation).or_default().push(meta);
+        }
+    }
+    let mut complete: Vec<(u64, Vec<CheckpointMeta>)> = generations.into_iter()
+        .filter(|(_, metas)| metas.first().is_some_and(|first| {
+            (0..first.size).all(|rank| metas.iter().any(|meta| meta.rank == rank && meta.size == first.size))
+        }))
+        .collect();
+    complete.sort_by_key(|(generation, _)| *generation);
+    complete.pop().map(|(_, mut metas)| {
+        metas.sort_by_key(|meta| meta.rank);
+        metas
+    })
+}
+
+// Split the unscanned byte ranges evenly over `size` ranks, cutting only at newlines
+fn assign_remaining_segments(input_file: &str, remaining: &[(u64, u64)], size: usize) -> Vec<Vec<ScanSegment>> {
+    let file = File::open(input_file).expect("Failed to open input file");
+    let mmap = unsafe { MmapOptions::new().map(&file).expect("Failed to map file") };
+    
+    let total: u64 = remaining.iter().map(|(start, end)| end - start).sum();
+    let mut plan = vec![Vec::new(); size];
+    let mut rank = 0;
+    let mut assigned = 0;
+    for &(mut start, end) in remaining {
+        while start < end {
+            let quota_end = (rank as u64 + 1) * total / size as u64;
+            let room = quota_end.saturating_sub(assigned);
+            if rank + 1 == size || start + room >= end {
+                plan[rank].push(ScanSegment::new(start, end));
+                assigned += end - start;
+                if assigned >= quota_end && rank + 1 < size {
+                    rank += 1;
+                }
+                break;
+            }
+            // The newline ending the line that contains the cut point
+            let cut = (next_line_start(&mmap, (start + room) as usize + 1) as u64)
+                .saturating_sub(1)
+                .clamp(start, end);
+            if cut > start {
+                plan[rank].push(ScanSegment::new(start, cut));
+                assigned += cut - start;
+                start = cut;
+            }
+            rank += 1;
+        }
+    }
+    plan
+}
+
+// Rank whose segments hold the line starting at `offset`, if it is still to be scanned
+pub(crate) fn segment_owner(plan: &[Vec<ScanSegment>], offset: u64) -> Option<usize> {
+    plan.iter().position(|segments| {
+        segments.iter().any(|segment| segment.contains_line(offset) && offset >= segment.resume_start())
+    })
+}
+
+// Where and how often checkpoints are written
+#[derive(Debug, Clone)]
+pub(crate) struct CheckpointOptions {
+    pub(crate) dir: PathBuf,
+    // Seconds between checkpoints of a rank
+    pub(crate) interval: f64,
+    pub(crate) resume: bool,
+}
+
+// Set up checkpointing for this run. Without --resume any previous checkpoints are discarded and
+// `fresh_plan` is used; with it the newest consistent generation is loaded, its unscanned ranges
+// are split over the current ranks and its states are merged, rank k of the old run going to
+// rank k % size. Returns the segments of every rank and this rank's starting state.
+pub(crate) fn start_checkpointing<C: Communicator>(
+    world: &C,
+    rank: usize,
+    size: usize,
+    options: &CheckpointOptions,
+    input_file: &str,
+    fresh_plan: Vec<Vec<ScanSegment>>,
+) -> (Checkpointer, Vec<Vec<ScanSegment>>, ScanState) {
+    let dir = options.dir.clone();
+    fs::create_dir_all(&dir).expect("Failed to create checkpoint directory");
+    let input = InputFingerprint::of(input_file);
+    
+    // Generations left over from runs that died while starting are skipped, never reused
+    let next_generation = checkpoint_files(&dir).iter().map(|(generation, _)| generation + 1).max().unwrap_or(0);
+    let previous = if options.resume { latest_consistent_checkpoint(&dir) } else { None };
+    let (generation, plan, state) = match previous {
+        Some(metas) => {
+            if let Some(meta) = metas.iter().find(|meta| meta.input != input) {
+                panic!(
+                    "Input {} changed since checkpoint generation {} was written ({:?} now {:?})",
+                    input_file, meta.generation, meta.input, input
+                );
+            }
+            let mut remaining: Vec<(u64, u64)> = metas.iter()
+                .flat_map(|meta| meta.segments.iter().map(|segment| (segment.resume_start(), segment.end)))
+                .filter(|(start, end)| start < end)
+                .collect();
+            remaining.sort();
+            
+            let mut aggregates = Vec::new();
+            let mut state = ScanState::default();
+            for meta in metas.iter().filter(|meta| meta.rank % size == rank) {
+                let previous_state = read_checkpoint_state(&dir, meta);
+                aggregates.push(previous_state.aggregates);
+                state.partitioned.graph.merge(previous_state.partitioned.graph);
+                state.partitioned.thread_nodes.extend(previous_state.partitioned.thread_nodes);
+                state.lines_processed += previous_state.lines_processed;
+            }
+            state.aggregates = merge_aggregates(aggregates);
+            
+            if rank == 0 {
+                let bytes: u64 = remaining.iter().map(|(start, end)| end - start).sum();
+                println!(
+                    "Resuming from checkpoint generation {} of {} ranks with {} bytes left to scan",
+                    metas[0].generation, metas[0].size, bytes
+                );
+            }
+            (next_generation, assign_remaining_segments(input_file, &remaining, size), state)
+        }
+        None => {
+            if options.resume && rank == 0 {
+                println!("No consistent checkpoint in {}, starting from the beginning", dir.display());
+            }
+            (next_generation, fresh_plan, ScanState::default())
+        }
+    };
+    
+    // Old generations are only removed once every rank has written the new one
+    world.barrier();
+    let mut checkpointer = Checkpointer {
+        dir,
+        generation,
+        rank,
+        size,
+        input,
+        interval: options.interval,
+        last_write: Instant::now(),
+        unchecked_lines: 0,
+    };
+    checkpointer.write(&plan[rank], &state);
+    world.barrier();
+    if rank == 0 {
+        for (old_generation, path) in checkpoint_files(&checkpointer.dir) {
+            if old_generation != generation {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+    
+    (checkpointer, plan, state)
+}
+
+// Remove the checkpoints of a finished run
+pub(crate) fn remove_checkpoints(dir: &Path) {
+    for (_, path) in checkpoint_files(dir) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::for_each_chunk_line;
+    
+    #[test]
+    fn resumed_segments_cover_every_remaining_line_once() {
+        let path = std::env::temp_dir().join(format!("segments-{}.ndjson", std::process::id()));
+        let lines: Vec<String> = (0..200).map(|i| format!("{{\"n\":{}{}}}", i, " ".repeat(i % 7))).collect();
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let input = path.to_str().unwrap();
+        
+        // Two old ranks, each interrupted after its first 37 lines
+        let mut scanned = Vec::new();
+        let mut old_segments = Vec::new();
+        for (start, end) in [(0, 1500), (1500, fs::metadata(&path).unwrap().len())] {
+            let mut segment = ScanSegment::new(start, end);
+            let mut handled = 0;
+            let in_range = for_each_chunk_line(input, start, end, 64, |offset, line| {
+                if handled < 37 {
+                    scanned.push(line.to_string());
+                    segment.last_line = Some(offset);
+                    handled += 1;
+                }
+            });
+            assert!(handled == 37 && in_range > 37);
+            old_segments.push(segment);
+        }
+        let remaining: Vec<(u64, u64)> = old_segments.iter().map(|segment| (segment.resume_start(), segment.end)).collect();
+        
+        for size in [1, 3, 5] {
+            let mut seen = scanned.clone();
+            for segments in assign_remaining_segments(input, &remaining, size) {
+                for segment in segments {
+                    for_each_chunk_line(input, segment.start, segment.end, 64, |_, line| seen.push(line.to_string()));
+                }
+            }
+            seen.sort();
+            let mut expected = lines.clone();
+            expected.sort();
+            assert_eq!(seen, expected, "{} ranks", size);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
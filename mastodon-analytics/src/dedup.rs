@@ -0,0 +1,177 @@
+//! Keeping the latest copy of statuses exported more than once
+
+use std::collections::{HashMap, HashSet};
+use chrono::DateTime;
+use mpi::traits::*;
+use serde_json::{from_str, Value};
+use crate::{exchange_between_ranks, for_each_chunk_line, owner_rank};
+use crate::reblogs::ReblogMode;
+
+// Key identifying the same status across repeated exports: the URI, else the id, else the URL
+fn status_key(status: &Value) -> Option<String> {
+    ["uri", "id", "url"]
+        .iter()
+        .find_map(|field| status.get(field).and_then(|v| v.as_str()))
+        .map(String::from)
+}
+
+// Version of a status used to keep the latest of several copies, as milliseconds since the
+// epoch: the last edit (else the creation time), then the export's `@timestamp` to order
+// copies of the same revision. Comparing an edit time with an export time would let a stale
+// copy from a later export win over an edited one.
+type StatusVersion = (i64, i64);
+
+fn status_version(record: &Value, status: &Value) -> StatusVersion {
+    let time = |fields: &[Option<&Value>]| {
+        fields
+            .iter()
+            .flatten()
+            .filter_map(|v| v.as_str())
+            .find_map(|v| DateTime::parse_from_rfc3339(v).ok())
+            .map_or(i64::MIN, |time| time.timestamp_millis())
+    };
+    (
+        time(&[status.get("editedAt"), status.get("edited_at"), status.get("createdAt"), status.get("created_at")]),
+        time(&[record.get("@timestamp"), status.get("@timestamp")]),
+    )
+}
+
+// Key and version of the status a line is counted as. With `--reblogs original` a reblog
+// competes with the status it boosts, so each original is counted once.
+fn dedup_candidate(line: &str, reblog_mode: ReblogMode) -> Option<(String, StatusVersion)> {
+    let record: Value = from_str(line).ok()?;
+    let mut status = record.get("doc").unwrap_or(&record);
+    if reblog_mode == ReblogMode::Original {
+        if let Some(original) = status.get("reblog").filter(|v| v.is_object()) {
+            status = original;
+        }
+    }
+    Some((status_key(status)?, status_version(&record, status)))
+}
+
+// Hash-partition the status keys of every rank to their owner ranks, keep the latest copy of
+// each key and return the offsets of this rank's lines that lost to another copy.
+// Copies with the same version are resolved in favour of the later line in the file.
+pub(crate) fn find_duplicate_offsets<C: Communicator>(
+    world: &C,
+    rank: usize,
+    size: usize,
+    input_file: &str,
+    (local_start, local_end): (u64, u64),
+    max_buffer_size: usize,
+    reblog_mode: ReblogMode,
+) -> HashSet<u64> {
+    let mut outgoing: Vec<Vec<(String, StatusVersion, u64)>> = vec![Vec::new(); size];
+    for_each_chunk_line(input_file, local_start, local_end, max_buffer_size, |offset, line| {
+        if let Some((key, version)) = dedup_candidate(line, reblog_mode) {
+            outgoing[owner_rank(&key, size)].push((key, version, offset));
+        }
+    });
+    
+    // Latest copy of every owned key as (version, offset, source rank)
+    let mut latest: HashMap<String, (StatusVersion, u64, usize)> = HashMap::new();
+    let mut losers: Vec<Vec<u64>> = vec![Vec::new(); size];
+    for (source, candidates) in exchange_between_ranks(world, rank, size, outgoing).into_iter().enumerate() {
+        for (key, version, offset) in candidates {
+            let candidate = (version, offset, source);
+            match latest.get_mut(&key) {
+                Some(kept) => {
+                    let loser = if (candidate.0, candidate.1) > (kept.0, kept.1) {
+                        std::mem::replace(kept, candidate)
+                    } else {
+                        candidate
+                    };
+                    losers[loser.2].push(loser.1);
+                }
+                None => {
+                    latest.insert(key, candidate);
+                }
+            }
+        }
+    }
+    
+    exchange_between_ranks(world, rank, size, losers).into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{exported_status, mpi_universe, scratch_dir};
+    use serde_json::json;
+    use std::fs;
+    
+    #[test]
+    fn duplicates_keep_the_latest_copy_of_each_status() {
+        let uri = |id: &str| format!("https://mastodon.social/users/alice/statuses/{}", id);
+        let original = |fields: Value| from_str::<Value>(&exported_status(fields)).unwrap()["doc"].clone();
+        let lines = [
+            exported_status(json!({"uri": uri("1"), "sentiment": 0.1})),
+            exported_status(json!({"uri": uri("2")})),
+            exported_status(json!({"uri": uri("1"), "sentiment": 0.3, "editedAt": "2025-01-30T12:30:00.000Z"})),
+            exported_status(json!({"uri": uri("3"), "reblog": original(json!({"uri": uri("2")}))})),
+            // An older copy read after the edited one still loses
+            exported_status(json!({"uri": uri("1"), "sentiment": 0.1})),
+        ];
+        let dir = scratch_dir("dedup");
+        let path = dir.join("statuses.ndjson");
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let offsets: Vec<u64> = lines
+            .iter()
+            .scan(0, |offset, line| {
+                let start = *offset;
+                *offset += line.len() as u64 + 1;
+                Some(start)
+            })
+            .collect();
+        let len = fs::metadata(&path).unwrap().len();
+        let path = path.to_str().unwrap();
+        
+        let universe = mpi_universe();
+        let world = universe.world();
+        let include = find_duplicate_offsets(&world, 0, 1, path, (0, len), 1 << 20, ReblogMode::Include);
+        let original = find_duplicate_offsets(&world, 0, 1, path, (0, len), 1 << 20, ReblogMode::Original);
+        drop(universe);
+        
+        assert_eq!(include, HashSet::from([offsets[0], offsets[4]]));
+        // Counted as its original, the reblog and the status it boosts are one status
+        assert_eq!(original, HashSet::from([offsets[0], offsets[1], offsets[4]]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn duplicate_ties_keep_the_later_line() {
+        let uri = |id: &str| format!("https://mastodon.social/users/alice/statuses/{}", id);
+        let exported_at = |line: String, timestamp: &str| {
+            let mut record: Value = from_str(&line).unwrap();
+            record["@timestamp"] = json!(timestamp);
+            record.to_string()
+        };
+        let lines = [
+            // Identical copies: the later line wins
+            exported_status(json!({"uri": uri("1"), "sentiment": 0.1})),
+            exported_status(json!({"uri": uri("1"), "sentiment": 0.1})),
+            // The same revision exported twice: the later export wins even when it comes first
+            exported_at(exported_status(json!({"uri": uri("2")})), "2025-02-08T00:00:00Z"),
+            exported_at(exported_status(json!({"uri": uri("2")})), "2025-02-07T00:00:00Z"),
+            // Copies without any time keep the later line too
+            exported_status(json!({"uri": uri("3"), "createdAt": null})),
+            exported_status(json!({"uri": uri("3"), "createdAt": null})),
+        ];
+        let dir = scratch_dir("dedup-ties");
+        let path = dir.join("statuses.ndjson");
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let mut offsets = vec![0];
+        for line in &lines {
+            offsets.push(offsets.last().unwrap() + line.len() as u64 + 1);
+        }
+        let len = fs::metadata(&path).unwrap().len();
+        
+        let universe = mpi_universe();
+        let world = universe.world();
+        let losers = find_duplicate_offsets(&world, 0, 1, path.to_str().unwrap(), (0, len), 1 << 20, ReblogMode::Include);
+        drop(universe);
+        
+        assert_eq!(losers, HashSet::from([offsets[0], offsets[3], offsets[4]]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+//! Engagement against sentiment
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::path::Path;
+use crate::{print_ranking, write_csv, write_ranking, EngagedPost, EngagementStats, WeightedSentiment, ENGAGEMENT_HISTOGRAM_BINS};
+
+const SENTIMENT_DECILES: usize = 10;
+
+// Statuses of one sentiment decile and the sentiment range of the histogram bins it took
+#[derive(Debug, Default, Clone, Copy)]
+struct EngagementDecile {
+    range: Option<(f64, f64)>,
+    stats: EngagementStats,
+}
+
+// Cut the fine histogram into deciles of the post count. A bin goes whole to the decile its
+// middle post falls in, so a value shared by many statuses can leave a neighbouring decile empty.
+fn engagement_deciles(histogram: &[EngagementStats]) -> [EngagementDecile; SENTIMENT_DECILES] {
+    let mut deciles = [EngagementDecile::default(); SENTIMENT_DECILES];
+    let total: usize = histogram.iter().map(|stats| stats.posts).sum();
+    let width = 2.0 / ENGAGEMENT_HISTOGRAM_BINS as f64;
+    let mut preceding = 0;
+    for (i, stats) in histogram.iter().enumerate().filter(|(_, stats)| stats.posts > 0) {
+        let middle = preceding as f64 + stats.posts as f64 / 2.0;
+        let index = (middle * SENTIMENT_DECILES as f64 / total as f64) as usize;
+        let decile = &mut deciles[min(index, SENTIMENT_DECILES - 1)];
+        let (lower, upper) = (-1.0 + i as f64 * width, -1.0 + (i + 1) as f64 * width);
+        decile.range = Some(decile.range.map_or((lower, upper), |(first, _)| (first, upper)));
+        decile.stats.merge(stats);
+        preceding += stats.posts;
+    }
+    deciles
+}
+
+pub(crate) fn dump_engagement_by_sentiment(histogram: &[EngagementStats], output_dir: &Path) {
+    let lines: Vec<String> = engagement_deciles(histogram)
+        .iter()
+        .enumerate()
+        .map(|(i, decile)| {
+            let stats = &decile.stats;
+            match decile.range {
+                Some((lower, upper)) => format!(
+                    "{}. sentiment [{:+.3}, {:+.3}{} {} posts, mean {:.2} favourites, {:.2} reblogs, {:.2} replies",
+                    i + 1,
+                    lower,
+                    upper,
+                    if upper >= 1.0 { "]:" } else { "):" },
+                    stats.posts,
+                    stats.mean(stats.favourites),
+                    stats.mean(stats.reblogs),
+                    stats.mean(stats.replies)
+                ),
+                None => format!("{}. no posts of its own; tied sentiment values fill a neighbouring decile", i + 1),
+            }
+        })
+        .collect();
+    print_ranking("Engagement by Sentiment Decile", &lines);
+    write_ranking("Engagement by Sentiment Decile", "engagement_by_sentiment.txt", &lines, output_dir);
+}
+
+pub(crate) fn dump_engaged_posts(title: &str, file_name: &str, posts: &[EngagedPost], output_dir: &Path) {
+    let lines: Vec<String> = posts
+        .iter()
+        .enumerate()
+        .map(|(i, post)| {
+            format!(
+                "{}. {} by @{} with engagement {} ({} favourites, {} reblogs, {} replies) and sentiment {:+.4}",
+                i + 1,
+                post.url.as_deref().or(post.status_id.as_deref()).unwrap_or("unknown status"),
+                post.acct.as_deref().unwrap_or("unknown"),
+                post.engagement,
+                post.favourites,
+                post.reblogs,
+                post.replies,
+                post.sentiment
+            )
+        })
+        .collect();
+    print_ranking(title, &lines);
+    write_ranking(title, file_name, &lines, output_dir);
+}
+
+pub(crate) fn dump_hour_engagement(
+    hour_engagement: &HashMap<String, WeightedSentiment>,
+    hour_sentiment: &HashMap<String, f64>,
+    hour_count: &HashMap<String, usize>,
+    output_dir: &Path,
+) {
+    let mut hours: Vec<_> = hour_engagement.iter().collect();
+    hours.sort_by(|a, b| a.0.cmp(b.0));
+    
+    let rows = hours.into_iter().map(|(hour, weighted)| {
+        let posts = hour_count.get(hour).copied().unwrap_or(0);
+        let mean_sentiment = if posts == 0 {
+            0.0
+        } else {
+            hour_sentiment.get(hour).copied().unwrap_or(0.0) / posts as f64
+        };
+        format!("{}:00,{},{},{},{}", hour, posts, weighted.weight, mean_sentiment, weighted.mean())
+    });
+    write_csv(
+        "hour_engagement.csv",
+        "hour,posts,engagement_weight,mean_sentiment,engagement_weighted_sentiment",
+        rows,
+        output_dir,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{aggregate, exported_status};
+    use crate::ProcessingOptions;
+    use serde_json::json;
+    
+    #[test]
+    fn engagement_deciles_split_statuses_by_post_count() {
+        // Most statuses are mildly positive, so equal-width bins would put them all in one bin
+        let lines: Vec<String> = (0..20)
+            .map(|i| exported_status(json!({"sentiment": 0.01 * i as f64, "favouritesCount": i})))
+            .collect();
+        let (aggregates, _) = aggregate(&lines, &ProcessingOptions::default());
+        let deciles = engagement_deciles(&aggregates.engagement_histogram);
+        for (i, decile) in deciles.iter().enumerate() {
+            assert_eq!(decile.stats.posts, 2);
+            assert!((decile.stats.mean(decile.stats.favourites) - (2 * i) as f64 - 0.5).abs() < 1e-9);
+        }
+        let (lower, upper) = deciles[9].range.unwrap();
+        assert!(lower < 0.18 && upper > 0.19);
+        
+        // Ties stay together and leave the deciles they cover empty
+        let mut lines: Vec<String> = (0..5).map(|_| exported_status(json!({"sentiment": 0.0}))).collect();
+        lines.extend((1..=5).map(|i| exported_status(json!({"sentiment": 0.1 * i as f64}))));
+        let (aggregates, _) = aggregate(&lines, &ProcessingOptions::default());
+        let deciles = engagement_deciles(&aggregates.engagement_histogram);
+        let posts: Vec<usize> = deciles.iter().map(|decile| decile.stats.posts).collect();
+        assert_eq!(posts, [0, 0, 5, 0, 0, 1, 1, 1, 1, 1]);
+        assert!(deciles[0].range.is_none());
+    }
+}
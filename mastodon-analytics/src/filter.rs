@@ -0,0 +1,445 @@
+//! Record filter expressions (`--where`)
+
+use serde_json::Value;
+
+// Comparison operators of the filter language
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Operand {
+    // Dotted path into the status JSON, e.g. `account.followersCount`
+    Field(Vec<String>),
+    Literal(Value),
+}
+
+// A compiled `--where` expression, evaluated against the JSON of every status
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FilterExpr {
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Compare(Operand, CompareOp, Operand),
+    // A bare operand, true unless null, false, 0, "" or []
+    Truthy(Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FilterParseError {
+    message: String,
+    // 1-based character column of the offending input
+    column: usize,
+}
+
+impl std::fmt::Display for FilterParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+
+impl std::error::Error for FilterParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum FilterToken {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    True,
+    False,
+    Null,
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Op(CompareOp),
+}
+
+impl FilterToken {
+    fn describe(&self) -> String {
+        match self {
+            FilterToken::Ident(name) => format!("field '{}'", name),
+            FilterToken::Str(value) => format!("string \"{}\"", value),
+            FilterToken::Num(value) => format!("number {}", value),
+            FilterToken::True => "'true'".to_string(),
+            FilterToken::False => "'false'".to_string(),
+            FilterToken::Null => "'null'".to_string(),
+            FilterToken::LParen => "'('".to_string(),
+            FilterToken::RParen => "')'".to_string(),
+            FilterToken::And => "'&&'".to_string(),
+            FilterToken::Or => "'||'".to_string(),
+            FilterToken::Not => "'!'".to_string(),
+            FilterToken::Op(op) => format!("'{}'", match op {
+                CompareOp::Eq => "==",
+                CompareOp::Ne => "!=",
+                CompareOp::Lt => "<",
+                CompareOp::Le => "<=",
+                CompareOp::Gt => ">",
+                CompareOp::Ge => ">=",
+                CompareOp::Contains => "contains",
+            }),
+        }
+    }
+}
+
+fn filter_error(message: impl Into<String>, index: usize) -> FilterParseError {
+    FilterParseError { message: message.into(), column: index + 1 }
+}
+
+// Split an expression into tokens paired with their character index
+fn tokenize_filter(source: &str) -> Result<Vec<(FilterToken, usize)>, FilterParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let next = chars.get(i + 1).copied();
+        let token = match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => FilterToken::LParen,
+            ')' => FilterToken::RParen,
+            '&' if next == Some('&') => FilterToken::And,
+            '|' if next == Some('|') => FilterToken::Or,
+            '=' if next == Some('=') => FilterToken::Op(CompareOp::Eq),
+            '!' if next == Some('=') => FilterToken::Op(CompareOp::Ne),
+            '<' if next == Some('=') => FilterToken::Op(CompareOp::Le),
+            '>' if next == Some('=') => FilterToken::Op(CompareOp::Ge),
+            '!' => FilterToken::Not,
+            '<' => FilterToken::Op(CompareOp::Lt),
+            '>' => FilterToken::Op(CompareOp::Gt),
+            '&' => return Err(filter_error("expected '&&'", start)),
+            '|' => return Err(filter_error("expected '||'", start)),
+            '=' => return Err(filter_error("expected '=='", start)),
+            quote @ ('"' | '\'') => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(filter_error("unterminated string", start)),
+                        Some(&c) if c == quote => break,
+                        Some('\\') => {
+                            i += 1;
+                            match chars.get(i) {
+                                Some('n') => value.push('\n'),
+                                Some('t') => value.push('\t'),
+                                Some(&c) => value.push(c),
+                                None => return Err(filter_error("unterminated string", start)),
+                            }
+                        }
+                        Some(&c) => value.push(c),
+                    }
+                    i += 1;
+                }
+                FilterToken::Str(value)
+            }
+            c if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                i += 1;
+                while i < chars.len()
+                    && (chars[i].is_ascii_digit()
+                        || chars[i] == '.'
+                        || matches!(chars[i], 'e' | 'E')
+                        || (matches!(chars[i], '+' | '-') && matches!(chars[i - 1], 'e' | 'E')))
+                {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let value = literal
+                    .parse()
+                    .map_err(|_| filter_error(format!("invalid number '{}'", literal), start))?;
+                tokens.push((FilterToken::Num(value), start));
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' || c == '@' => {
+                i += 1;
+                while i < chars.len() && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.' | '@')) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let token = match word.as_str() {
+                    "true" => FilterToken::True,
+                    "false" => FilterToken::False,
+                    "null" => FilterToken::Null,
+                    "contains" => FilterToken::Op(CompareOp::Contains),
+                    _ if word.split('.').any(str::is_empty) => {
+                        return Err(filter_error(format!("invalid field name '{}'", word), start))
+                    }
+                    _ => FilterToken::Ident(word),
+                };
+                tokens.push((token, start));
+                continue;
+            }
+            c => return Err(filter_error(format!("unexpected character '{}'", c), start)),
+        };
+        i += match token {
+            FilterToken::LParen | FilterToken::RParen | FilterToken::Not => 1,
+            FilterToken::Op(CompareOp::Lt | CompareOp::Gt) => 1,
+            _ => 2,
+        };
+        tokens.push((token, start));
+    }
+    Ok(tokens)
+}
+
+// Recursive-descent parser; precedence from loosest to tightest is ||, &&, !, comparison
+struct FilterParser {
+    tokens: Vec<(FilterToken, usize)>,
+    index: usize,
+    end: usize,
+}
+
+impl FilterParser {
+    fn peek(&self) -> Option<&FilterToken> {
+        self.tokens.get(self.index).map(|(token, _)| token)
+    }
+    
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map_or(self.end, |(_, position)| *position)
+    }
+    
+    fn unexpected(&self, expected: &str) -> FilterParseError {
+        match self.peek() {
+            Some(token) => filter_error(format!("expected {}, found {}", expected, token.describe()), self.position()),
+            None => filter_error(format!("expected {}, found end of expression", expected), self.end),
+        }
+    }
+    
+    fn parse_or(&mut self) -> Result<FilterExpr, FilterParseError> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&FilterToken::Or) {
+            self.index += 1;
+            left = FilterExpr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+    
+    fn parse_and(&mut self) -> Result<FilterExpr, FilterParseError> {
+        let mut left = self.parse_not()?;
+        while self.peek() == Some(&FilterToken::And) {
+            self.index += 1;
+            left = FilterExpr::And(Box::new(left), Box::new(self.parse_not()?));
+        }
+        Ok(left)
+    }
+    
+    fn parse_not(&mut self) -> Result<FilterExpr, FilterParseError> {
+        if self.peek() == Some(&FilterToken::Not) {
+            self.index += 1;
+            return Ok(FilterExpr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+    
+    fn parse_comparison(&mut self) -> Result<FilterExpr, FilterParseError> {
+        if self.peek() == Some(&FilterToken::LParen) {
+            self.index += 1;
+            let inner = self.parse_or()?;
+            if self.peek() != Some(&FilterToken::RParen) {
+                return Err(self.unexpected("')'"));
+            }
+            self.index += 1;
+            return Ok(inner);
+        }
+        
+        let left = self.parse_operand()?;
+        match self.peek() {
+            Some(&FilterToken::Op(op)) => {
+                self.index += 1;
+                Ok(FilterExpr::Compare(left, op, self.parse_operand()?))
+            }
+            _ => Ok(FilterExpr::Truthy(left)),
+        }
+    }
+    
+    fn parse_operand(&mut self) -> Result<Operand, FilterParseError> {
+        let operand = match self.peek() {
+            Some(FilterToken::Ident(name)) => Operand::Field(name.split('.').map(String::from).collect()),
+            Some(FilterToken::Str(value)) => Operand::Literal(Value::String(value.clone())),
+            Some(FilterToken::Num(value)) => Operand::Literal(
+                serde_json::Number::from_f64(*value).map_or(Value::Null, Value::Number),
+            ),
+            Some(FilterToken::True) => Operand::Literal(Value::Bool(true)),
+            Some(FilterToken::False) => Operand::Literal(Value::Bool(false)),
+            Some(FilterToken::Null) => Operand::Literal(Value::Null),
+            _ => return Err(self.unexpected("a field name or value")),
+        };
+        self.index += 1;
+        Ok(operand)
+    }
+}
+
+// Value at `path` below `root`
+fn field_at<'a>(root: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(root, |value, key| value.get(key))
+}
+
+fn filter_values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => left == right,
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(flag) => *flag,
+        Value::Number(number) => number.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+impl FilterExpr {
+    pub(crate) fn parse(source: &str) -> Result<Self, FilterParseError> {
+        let mut parser = FilterParser {
+            tokens: tokenize_filter(source)?,
+            index: 0,
+            end: source.chars().count(),
+        };
+        let expr = parser.parse_or()?;
+        if parser.peek().is_some() {
+            return Err(parser.unexpected("'&&', '||' or end of expression"));
+        }
+        Ok(expr)
+    }
+    
+    // Evaluate against a status. Fields are looked up on the status first and then on its
+    // account, so `followersCount` works as well as `account.followersCount`.
+    // Missing fields are null; ordering between different types is false.
+    pub(crate) fn matches(&self, status: &Value) -> bool {
+        match self {
+            FilterExpr::And(left, right) => left.matches(status) && right.matches(status),
+            FilterExpr::Or(left, right) => left.matches(status) || right.matches(status),
+            FilterExpr::Not(inner) => !inner.matches(status),
+            FilterExpr::Truthy(operand) => is_truthy(&Self::resolve(operand, status)),
+            FilterExpr::Compare(left, op, right) => {
+                let (left, right) = (Self::resolve(left, status), Self::resolve(right, status));
+                match op {
+                    CompareOp::Eq => filter_values_equal(&left, &right),
+                    CompareOp::Ne => !filter_values_equal(&left, &right),
+                    CompareOp::Contains => match (&left, &right) {
+                        (Value::String(haystack), Value::String(needle)) => haystack.contains(needle.as_str()),
+                        // Tags and other object lists are matched on their `name`
+                        (Value::Array(items), needle) => items
+                            .iter()
+                            .any(|item| filter_values_equal(item.get("name").unwrap_or(item), needle)),
+                        _ => false,
+                    },
+                    CompareOp::Lt | CompareOp::Le | CompareOp::Gt | CompareOp::Ge => {
+                        let ordering = match (&left, &right) {
+                            (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+                            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+                            _ => None,
+                        };
+                        ordering.is_some_and(|ordering| match op {
+                            CompareOp::Lt => ordering.is_lt(),
+                            CompareOp::Le => ordering.is_le(),
+                            CompareOp::Gt => ordering.is_gt(),
+                            _ => ordering.is_ge(),
+                        })
+                    }
+                }
+            }
+        }
+    }
+    
+    fn resolve(operand: &Operand, status: &Value) -> Value {
+        match operand {
+            Operand::Literal(value) => value.clone(),
+            Operand::Field(path) => field_at(status, path)
+                .or_else(|| status.get("account").and_then(|account| field_at(account, path)))
+                .cloned()
+                .unwrap_or(Value::Null),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    fn status() -> Value {
+        json!({
+            "language": "en",
+            "createdAt": "2025-02-01T10:00:00.000Z",
+            "sensitive": false,
+            "favouritesCount": 3,
+            "spoilerText": "",
+            "tags": [
+                {"name": "rust", "url": "https://example.org/tags/rust"},
+                {"name": "mpi", "url": "https://example.org/tags/mpi"}
+            ],
+            "filtered": ["spam"],
+            "content": "<p>Hello world</p>",
+            "account": {"acct": "alice@example.org", "followersCount": 250, "bot": false}
+        })
+    }
+    
+    pub(crate) fn matches(expression: &str) -> bool {
+        FilterExpr::parse(expression).expect("expression should parse").matches(&status())
+    }
+    
+    #[test]
+    fn filter_compares_fields_and_literals() {
+        assert!(matches(r#"language == "en" && followersCount > 100 && createdAt >= "2025-01-01""#));
+        assert!(matches("account.followersCount == 250"));
+        assert!(matches("favouritesCount >= 3 && favouritesCount < 3.5"));
+        assert!(!matches(r#"language != "en""#));
+        assert!(matches(r#"content contains "Hello" && tags contains "mpi""#));
+        assert!(!matches(r#"tags contains "go""#));
+        assert!(matches(r#"filtered contains "spam""#));
+    }
+    
+    #[test]
+    fn filter_handles_missing_fields_and_mismatched_types() {
+        assert!(matches("editedAt == null"));
+        assert!(!matches("editedAt"));
+        assert!(!matches(r#"followersCount > "100""#));
+        assert!(!matches("missing.deeply.nested > 0"));
+    }
+    
+    #[test]
+    fn filter_respects_precedence_and_negation() {
+        // && binds tighter than ||
+        assert!(matches(r#"language == "de" && sensitive || bot == false"#));
+        assert!(!matches(r#"language == "de" && (sensitive || bot == false)"#));
+        assert!(matches("!sensitive && !bot"));
+        assert!(!matches("!(favouritesCount > 1)"));
+        assert_eq!(
+            FilterExpr::parse("a || b && c").unwrap(),
+            FilterExpr::Or(
+                Box::new(FilterExpr::Truthy(Operand::Field(vec!["a".to_string()]))),
+                Box::new(FilterExpr::And(
+                    Box::new(FilterExpr::Truthy(Operand::Field(vec!["b".to_string()]))),
+                    Box::new(FilterExpr::Truthy(Operand::Field(vec!["c".to_string()]))),
+                )),
+            )
+        );
+    }
+    
+    #[test]
+    fn filter_reports_parse_errors_with_columns() {
+        let error = |expression: &str| FilterExpr::parse(expression).unwrap_err().to_string();
+        assert_eq!(error(r#"language = "en""#), "expected '==' at column 10");
+        assert_eq!(error(r#"language == "en"#), "unterminated string at column 13");
+        assert_eq!(error("followersCount >"), "expected a field name or value, found end of expression at column 17");
+        assert_eq!(error("(bot || group"), "expected ')', found end of expression at column 14");
+        assert_eq!(error("bot group"), "expected '&&', '||' or end of expression, found field 'group' at column 5");
+        assert_eq!(error("bot & group"), "expected '&&' at column 5");
+        assert_eq!(error("score > 1 # comment"), "unexpected character '#' at column 11");
+        assert_eq!(error("account..bot"), "invalid field name 'account..bot' at column 1");
+    }
+}
@@ -0,0 +1,144 @@
+//! Account interaction graph: partitioning across ranks and export
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use mpi::traits::*;
+use crate::{create_output_file, exchange_between_ranks, owner_rank, write_lines, InteractionGraph};
+
+// Redistribute the per-rank graphs so that each rank holds every edge whose source it owns
+pub(crate) fn partition_graph<C: Communicator>(world: &C, rank: usize, size: usize, local_graph: InteractionGraph) -> InteractionGraph {
+    let mut outgoing: Vec<InteractionGraph> = (0..size).map(|_| InteractionGraph::default()).collect();
+    for (source, targets) in local_graph.edges {
+        let part = &mut outgoing[owner_rank(&source, size)];
+        for target in targets.keys().chain(std::iter::once(&source)) {
+            if let Some(label) = local_graph.labels.get(target) {
+                part.add_label(target, label);
+            }
+        }
+        part.edges.insert(source, targets);
+    }
+    
+    let mut partition = InteractionGraph::default();
+    for part in exchange_between_ranks(world, rank, size, outgoing) {
+        partition.merge(part);
+    }
+    partition
+}
+
+fn graph_label<'a>(graph: &'a InteractionGraph, id: &'a str) -> &'a str {
+    graph.labels.get(id).map(String::as_str).unwrap_or(id)
+}
+
+pub(crate) fn dump_graph_edge_list(graph: &InteractionGraph, rank: usize, output_dir: &Path) {
+    // Every rank writes the edges it owns
+    let file_name = format!("interaction_edges.part-{:05}.tsv", rank);
+    let header = "source_id\ttarget_id\tsource_acct\ttarget_acct\tmentions\treplies\tweight\tmean_sentiment".to_string();
+    let rows = graph.edges.iter().flat_map(|(source, targets)| {
+        targets.iter().map(move |(target, edge)| {
+            format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                source,
+                target,
+                graph_label(graph, source),
+                graph_label(graph, target),
+                edge.mentions,
+                edge.replies,
+                edge.weight(),
+                edge.mean_sentiment()
+            )
+        })
+    });
+    write_lines(&file_name, std::iter::once(header).chain(rows), output_dir);
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub(crate) fn dump_graph_graphml(graph: &InteractionGraph, output_dir: &Path) {
+    let mut writer = create_output_file(output_dir, "interaction_graph.graphml");
+    
+    let mut nodes: HashSet<&str> = HashSet::new();
+    for (source, targets) in &graph.edges {
+        nodes.insert(source);
+        nodes.extend(targets.keys().map(String::as_str));
+    }
+    let mut nodes: Vec<&str> = nodes.into_iter().collect();
+    nodes.sort();
+    
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#).expect("Failed to write to file");
+    writeln!(writer, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#).expect("Failed to write to file");
+    writeln!(writer, r#"  <key id="acct" for="node" attr.name="acct" attr.type="string"/>"#).expect("Failed to write to file");
+    writeln!(writer, r#"  <key id="mentions" for="edge" attr.name="mentions" attr.type="int"/>"#).expect("Failed to write to file");
+    writeln!(writer, r#"  <key id="replies" for="edge" attr.name="replies" attr.type="int"/>"#).expect("Failed to write to file");
+    writeln!(writer, r#"  <key id="weight" for="edge" attr.name="weight" attr.type="int"/>"#).expect("Failed to write to file");
+    writeln!(writer, r#"  <key id="sentiment" for="edge" attr.name="mean_sentiment" attr.type="double"/>"#).expect("Failed to write to file");
+    writeln!(writer, r#"  <graph id="interactions" edgedefault="directed">"#).expect("Failed to write to file");
+    
+    for node in nodes {
+        writeln!(
+            writer,
+            r#"    <node id="{}"><data key="acct">{}</data></node>"#,
+            xml_escape(node),
+            xml_escape(graph_label(graph, node))
+        )
+        .expect("Failed to write to file");
+    }
+    for (source, targets) in &graph.edges {
+        for (target, edge) in targets {
+            writeln!(
+                writer,
+                r#"    <edge source="{}" target="{}"><data key="mentions">{}</data><data key="replies">{}</data><data key="weight">{}</data><data key="sentiment">{}</data></edge>"#,
+                xml_escape(source),
+                xml_escape(target),
+                edge.mentions,
+                edge.replies,
+                edge.weight(),
+                edge.mean_sentiment()
+            )
+            .expect("Failed to write to file");
+        }
+    }
+    
+    writeln!(writer, "  </graph>").expect("Failed to write to file");
+    writeln!(writer, "</graphml>").expect("Failed to write to file");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{aggregate, exported_status};
+    use crate::ProcessingOptions;
+    use serde_json::json;
+    
+    #[test]
+    fn replies_to_a_mentioned_account_count_once() {
+        let mention = |id: &str, acct: &str| json!({"id": id, "acct": acct, "username": acct});
+        let lines = [
+            // Alice replies to Bob, mentioning Bob (as every reply does) and Carol
+            exported_status(json!({
+                "inReplyToAccountId": "2",
+                "mentions": [mention("2", "bob"), mention("3", "carol@example.org")],
+                "sentiment": 0.5,
+            })),
+            // Alice mentions Bob again without replying
+            exported_status(json!({"mentions": [mention("2", "bob")], "sentiment": -0.1})),
+        ];
+        let options = ProcessingOptions { build_graph: true, ..Default::default() };
+        let (_, partitioned) = aggregate(&lines, &options);
+        let graph = &partitioned.graph;
+        
+        let alice = &graph.edges["109491602528257114"];
+        assert_eq!((alice["2"].replies, alice["2"].mentions), (1, 1));
+        assert!((alice["2"].mean_sentiment() - 0.2).abs() < 1e-12);
+        assert_eq!((alice["3"].replies, alice["3"].mentions), (0, 1));
+        assert_eq!(graph.edge_count(), 2);
+        assert_eq!(graph.labels["2"], "bob");
+        assert_eq!(graph.labels["109491602528257114"], "alice@mastodon.social");
+    }
+}
@@ -0,0 +1,235 @@
+//! Sentiment grouped by composite --group-by keys
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use crate::{csv_field, status_time, write_csv, Aggregates, MastodonData, ProcessingOptions, SentimentSummary, UNKNOWN_LANGUAGE, UNKNOWN_VISIBILITY};
+use crate::categories::status_categories;
+
+// Separator between the values of a composite --group-by key
+const GROUP_KEY_SEPARATOR: char = '\t';
+
+// A field statuses can be grouped by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum GroupDimension {
+    Hour,
+    Day,
+    Month,
+    Weekday,
+    HourOfDay,
+    Language,
+    Instance,
+    User,
+    Hashtag,
+    Visibility,
+    Account,
+}
+
+impl GroupDimension {
+    const ALL: [GroupDimension; 11] = [
+        GroupDimension::Hour,
+        GroupDimension::Day,
+        GroupDimension::Month,
+        GroupDimension::Weekday,
+        GroupDimension::HourOfDay,
+        GroupDimension::Language,
+        GroupDimension::Instance,
+        GroupDimension::User,
+        GroupDimension::Hashtag,
+        GroupDimension::Visibility,
+        GroupDimension::Account,
+    ];
+    
+    fn name(&self) -> &'static str {
+        match self {
+            GroupDimension::Hour => "hour",
+            GroupDimension::Day => "day",
+            GroupDimension::Month => "month",
+            GroupDimension::Weekday => "weekday",
+            GroupDimension::HourOfDay => "hour_of_day",
+            GroupDimension::Language => "language",
+            GroupDimension::Instance => "instance",
+            GroupDimension::User => "user",
+            GroupDimension::Hashtag => "hashtag",
+            GroupDimension::Visibility => "visibility",
+            GroupDimension::Account => "account",
+        }
+    }
+    
+    // Values of this dimension for a status; empty if the status has none (it is then left out),
+    // several for hashtags
+    fn values(&self, mastodon_data: &MastodonData, created: Option<DateTime<Utc>>) -> Vec<String> {
+        let time_value = |format: &str| created.map(|time| time.format(format).to_string()).into_iter().collect();
+        match self {
+            GroupDimension::Hour => time_value("%Y-%m-%d %H:00"),
+            GroupDimension::Day => time_value("%Y-%m-%d"),
+            GroupDimension::Month => time_value("%Y-%m"),
+            GroupDimension::Weekday => time_value("%a"),
+            GroupDimension::HourOfDay => time_value("%H"),
+            GroupDimension::Language => {
+                vec![mastodon_data.language.clone().unwrap_or_else(|| UNKNOWN_LANGUAGE.to_string())]
+            }
+            GroupDimension::Instance => mastodon_data.instance.iter().cloned().collect(),
+            GroupDimension::User => mastodon_data.acct.iter().chain(&mastodon_data.user_id).take(1).cloned().collect(),
+            GroupDimension::Hashtag => mastodon_data.tags.clone(),
+            GroupDimension::Visibility => {
+                vec![mastodon_data.visibility.clone().unwrap_or_else(|| UNKNOWN_VISIBILITY.to_string())]
+            }
+            GroupDimension::Account => vec![status_categories(mastodon_data)[0].1.to_string()],
+        }
+    }
+}
+
+// Parse a comma-separated list of dimensions such as `hour,language`
+pub(crate) fn parse_group_by(value: &str) -> Result<Vec<GroupDimension>, String> {
+    let dimensions = value
+        .split(',')
+        .map(|name| {
+            let name = name.trim().to_lowercase();
+            GroupDimension::ALL
+                .iter()
+                .copied()
+                .find(|dimension| dimension.name() == name)
+                .ok_or_else(|| {
+                    let known: Vec<&str> = GroupDimension::ALL.iter().map(GroupDimension::name).collect();
+                    format!("unknown dimension '{}', expected one of {}", name, known.join(", "))
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if dimensions.is_empty() {
+        return Err("expected at least one dimension".to_string());
+    }
+    Ok(dimensions)
+}
+
+pub(crate) fn group_by_name(dimensions: &[GroupDimension]) -> String {
+    dimensions.iter().map(GroupDimension::name).collect::<Vec<_>>().join(",")
+}
+
+// Add a status to every composite key of every --group-by specification it falls under
+pub(crate) fn add_to_groups(mastodon_data: &MastodonData, sentiment: f64, aggregates: &mut Aggregates, options: &ProcessingOptions) {
+    let created = mastodon_data.created_at.as_deref().and_then(status_time);
+    for dimensions in &options.group_by {
+        // Cartesian product of the values of every dimension
+        let mut keys = vec![String::new()];
+        for (i, dimension) in dimensions.iter().enumerate() {
+            // A value must not split the key it becomes part of
+            let values: Vec<String> = dimension.values(mastodon_data, created)
+                .into_iter()
+                .map(|value| value.replace(GROUP_KEY_SEPARATOR, " "))
+                .collect();
+            keys = keys
+                .iter()
+                .flat_map(|prefix| {
+                    values.iter().map(move |value| {
+                        if i == 0 {
+                            value.clone()
+                        } else {
+                            format!("{}{}{}", prefix, GROUP_KEY_SEPARATOR, value)
+                        }
+                    })
+                })
+                .collect();
+        }
+        
+        let groups = aggregates.grouped.entry(group_by_name(dimensions)).or_default();
+        for key in keys {
+            groups
+                .entry(key)
+                .and_modify(|summary| summary.add(sentiment))
+                .or_insert_with(|| SentimentSummary::new(sentiment));
+        }
+    }
+}
+
+pub(crate) fn dump_grouped_table(dimensions: &[GroupDimension], groups: &HashMap<String, SentimentSummary>, output_dir: &Path) {
+    let names: Vec<&str> = dimensions.iter().map(GroupDimension::name).collect();
+    let file_name = format!("group_by_{}.csv", names.join("_"));
+    
+    let mut keys: Vec<_> = groups.keys().collect();
+    keys.sort();
+    
+    let rows = keys.into_iter().map(|key| {
+        let summary = &groups[key];
+        let values: Vec<Cow<str>> = key.split(GROUP_KEY_SEPARATOR).map(csv_field).collect();
+        format!(
+            "{},{},{},{},{},{}",
+            values.join(","),
+            summary.stats.count,
+            summary.stats.sum,
+            summary.stats.mean(),
+            summary.min,
+            summary.max
+        )
+    });
+    let header = format!("{},posts,total_sentiment,mean_sentiment,min_sentiment,max_sentiment", names.join(","));
+    write_csv(&file_name, &header, rows, output_dir);
+    
+    println!("Grouped by {}: {} groups written to {}", names.join(", "), groups.len(), file_name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{aggregate, exported_status, scratch_dir, tags};
+    use serde_json::json;
+    use std::fs;
+    
+    #[test]
+    fn group_by_builds_composite_keys_and_quotes_csv() {
+        let options = ProcessingOptions {
+            group_by: vec![parse_group_by("language,hashtag").unwrap(), parse_group_by("day").unwrap()],
+            ..ProcessingOptions::default()
+        };
+        let lines = [
+            // The same tag twice in one status counts once
+            exported_status(json!({"sentiment": 0.5, "tags": tags(&["rust", "Rust", "mpi"])})),
+            exported_status(json!({"sentiment": -0.5, "tags": tags(&["rust"])})),
+            exported_status(json!({"sentiment": 0.25, "language": "de,ch", "tags": tags(&["rust"])})),
+            exported_status(json!({"sentiment": 0.0})),
+        ];
+        let (aggregates, _) = aggregate(&lines, &options);
+        let by_tag = &aggregates.grouped["language,hashtag"];
+        assert_eq!(by_tag.len(), 3);
+        let rust = by_tag["en\trust"];
+        assert_eq!(rust.stats.count, 2);
+        assert_eq!((rust.stats.mean(), rust.min, rust.max), (0.0, -0.5, 0.5));
+        assert_eq!(aggregates.grouped["day"]["2025-01-30"].stats.count, 4);
+        
+        let output_dir = scratch_dir("group-by");
+        dump_grouped_table(&options.group_by[0], by_tag, &output_dir);
+        let written = fs::read_to_string(output_dir.join("group_by_language_hashtag.csv")).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[0], "language,hashtag,posts,total_sentiment,mean_sentiment,min_sentiment,max_sentiment");
+        assert_eq!(lines[1], "\"de,ch\",rust,1,0.25,0.25,0.25,0.25");
+        assert_eq!(lines.len(), 4);
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+    
+    #[test]
+    fn group_by_values_with_separators_stay_one_field() {
+        let options = ProcessingOptions {
+            group_by: vec![parse_group_by("instance,language").unwrap()],
+            ..ProcessingOptions::default()
+        };
+        let account = |acct: &str| json!({"id": "1", "username": "alice", "acct": acct});
+        let lines = [
+            exported_status(json!({"sentiment": 0.5, "language": "en,\"gb\"", "account": account("alice@a,b.example")})),
+            // A tab is the key separator, so it cannot stay in a value
+            exported_status(json!({"sentiment": -0.5, "language": "x\ty", "account": account("alice@c.example")})),
+        ];
+        let (aggregates, _) = aggregate(&lines, &options);
+        let groups = &aggregates.grouped["instance,language"];
+        assert!(groups.contains_key("a,b.example\ten,\"gb\""));
+        assert!(groups.contains_key("c.example\tx y"));
+        
+        let output_dir = scratch_dir("group-by-separators");
+        dump_grouped_table(&options.group_by[0], groups, &output_dir);
+        let written = fs::read_to_string(output_dir.join("group_by_instance_language.csv")).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[1], "\"a,b.example\",\"en,\"\"gb\"\"\",1,0.5,0.5,0.5,0.5");
+        assert_eq!(lines[2], "c.example,x y,1,-0.5,-0.5,-0.5,-0.5");
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+//! Sentiment reports grouped by hashtag or instance
+
+use std::collections::HashMap;
+use std::path::Path;
+use crate::{csv_field, print_ranking, write_csv, write_ranking, GroupStats, SentimentStats};
+
+pub(crate) fn group_mean_sentiment(group_stats: &HashMap<String, GroupStats>, min_posts: usize) -> HashMap<String, f64> {
+    group_stats
+        .iter()
+        .filter(|(_, group)| group.stats.count >= min_posts)
+        .map(|(key, group)| (key.clone(), group.stats.mean()))
+        .collect()
+}
+
+pub(crate) fn dump_group_ranking(
+    title: &str,
+    file_name: &str,
+    key_prefix: &str,
+    ranking: &[(String, f64)],
+    group_stats: &HashMap<String, GroupStats>,
+    output_dir: &Path,
+) {
+    let lines: Vec<String> = ranking
+        .iter()
+        .enumerate()
+        .map(|(i, (key, mean))| {
+            let group = &group_stats[key];
+            let users = match &group.users {
+                Some(users) => format!(" by about {} users ± {:.1}", users.count(), users.standard_error()),
+                None => String::new(),
+            };
+            format!(
+                "{}. {}{} with mean sentiment {:+.4} ({} posts{}, total sentiment {:+})",
+                i + 1, key_prefix, key, mean, group.stats.count, users, group.stats.sum
+            )
+        })
+        .collect();
+    print_ranking(title, &lines);
+    write_ranking(title, file_name, &lines, output_dir);
+}
+
+pub(crate) fn dump_group_table(file_name: &str, key_header: &str, group_stats: &HashMap<String, GroupStats>, output_dir: &Path) {
+    let mut groups: Vec<_> = group_stats.iter().collect();
+    groups.sort_by(|a, b| b.1.stats.count.cmp(&a.1.stats.count).then_with(|| a.0.cmp(b.0)));
+    
+    // Distinct users are HyperLogLog estimates, given with one standard error; both are left
+    // empty when they were not tracked
+    let header = format!("{},posts,distinct_users,distinct_users_standard_error,total_sentiment,mean_sentiment", key_header);
+    let rows = groups.into_iter().map(|(key, group)| {
+        let users = match &group.users {
+            Some(users) => format!("{},{:.2}", users.count(), users.standard_error()),
+            None => ",".to_string(),
+        };
+        format!("{},{},{},{},{}", csv_field(key), group.stats.count, users, group.stats.sum, group.stats.mean())
+    });
+    write_csv(file_name, &header, rows, output_dir);
+}
+
+pub(crate) fn dump_group_hourly(
+    file_name: &str,
+    key_header: &str,
+    group_hour_stats: &HashMap<String, HashMap<String, SentimentStats>>,
+    output_dir: &Path,
+) {
+    let mut keys: Vec<_> = group_hour_stats.keys().collect();
+    keys.sort();
+    
+    let mut rows = Vec::new();
+    for key in keys {
+        let mut hours: Vec<_> = group_hour_stats[key].iter().collect();
+        hours.sort_by(|a, b| a.0.cmp(b.0));
+        for (hour, stats) in hours {
+            rows.push(format!("{},{}:00,{},{},{}", csv_field(key), hour, stats.count, stats.sum, stats.mean()));
+        }
+    }
+    write_csv(file_name, &format!("{},hour,posts,total_sentiment,mean_sentiment", key_header), rows, output_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{aggregate, exported_status, tags};
+    use crate::{parse_list_option, top_n_by_value, HyperLogLog, ProcessingOptions};
+    use serde_json::json;
+    
+    #[test]
+    fn hashtag_sentiment_with_support_threshold_and_watchlist() {
+        let user = |id: &str| json!({"id": id, "username": id, "acct": id});
+        let lines = [
+            exported_status(json!({"tags": tags(&["Rust", "rust", "mpi"]), "sentiment": 0.5, "account": user("1")})),
+            exported_status(json!({"tags": tags(&["rust"]), "sentiment": 0.3, "account": user("2")})),
+            exported_status(json!({"tags": tags(&["mpi"]), "sentiment": -0.4, "account": user("1"),
+                "createdAt": "2025-01-30T13:00:00.000Z"})),
+            exported_status(json!({"tags": tags(&["news"]), "sentiment": -0.9})),
+        ];
+        let options = ProcessingOptions { hashtag_watchlist: parse_list_option("#MPI"), ..Default::default() };
+        let (aggregates, _) = aggregate(&lines, &options);
+        
+        // A tag repeated within one status counts once
+        let rust = &aggregates.hashtag_stats["rust"];
+        assert_eq!(rust.stats, SentimentStats { count: 2, sum: 0.8 });
+        assert_eq!(rust.users.as_ref().map(HyperLogLog::count), Some(2));
+        assert_eq!(aggregates.hashtag_stats["mpi"].users.as_ref().map(HyperLogLog::count), Some(1));
+        
+        let means = group_mean_sentiment(&aggregates.hashtag_stats, 2);
+        assert_eq!(means.len(), 2);
+        assert_eq!(top_n_by_value(&means, 1, true)[0].0, "rust");
+        assert_eq!(top_n_by_value(&means, 1, false)[0].0, "mpi");
+        
+        assert_eq!(aggregates.hashtag_hour_stats.keys().collect::<Vec<_>>(), ["mpi"]);
+        assert_eq!(aggregates.hashtag_hour_stats["mpi"]["2025-01-30 13"].sum, -0.4);
+        assert_eq!(aggregates.hashtag_hour_count["rust"]["2025-01-30 11"], 2);
+        
+        // Under --memory-limit the sums are kept but not the distinct-user sketches
+        let (bounded, _) = aggregate(&lines, &ProcessingOptions { memory_bounded: true, ..options });
+        assert_eq!(bounded.hashtag_stats["rust"].stats, rust.stats);
+        assert!(bounded.hashtag_stats.values().all(|group| group.users.is_none()));
+        assert!(bounded.instance_stats.values().all(|group| group.users.is_none()));
+    }
+    
+    #[test]
+    fn instance_sentiment_from_acct_or_profile_url() {
+        let account = |id: &str, acct: &str, url: &str| json!({"id": id, "username": id, "acct": acct, "url": url});
+        let lines = [
+            exported_status(json!({"sentiment": 0.4, "account": account("1", "alice@Mastodon.Social", "https://mastodon.social/@alice")})),
+            // Local accounts have a bare `acct`; the domain comes from the profile URL
+            exported_status(json!({"sentiment": 0.2, "account": account("2", "bob", "https://fosstodon.org/@bob")})),
+            exported_status(json!({"sentiment": -0.6, "account": account("3", "carol@fosstodon.org", "https://fosstodon.org/@carol"),
+                "createdAt": "2025-01-30T14:00:00.000Z"})),
+        ];
+        let (aggregates, _) = aggregate(&lines, &ProcessingOptions::default());
+        
+        let mut instances: Vec<_> = aggregates.instance_stats.keys().cloned().collect();
+        instances.sort();
+        assert_eq!(instances, ["fosstodon.org", "mastodon.social"]);
+        let fosstodon = &aggregates.instance_stats["fosstodon.org"];
+        assert_eq!(fosstodon.stats.count, 2);
+        assert!((fosstodon.stats.mean() + 0.2).abs() < 1e-12);
+        assert_eq!(fosstodon.users.as_ref().map(HyperLogLog::count), Some(2));
+        assert_eq!(aggregates.instance_hour_stats["fosstodon.org"]["2025-01-30 14"].sum, -0.6);
+        
+        let means = group_mean_sentiment(&aggregates.instance_stats, 1);
+        assert_eq!(top_n_by_value(&means, 1, true)[0].0, "mastodon.social");
+        assert_eq!(top_n_by_value(&means, 1, false)[0].0, "fosstodon.org");
+    }
+}
@@ -0,0 +1,184 @@
+//! Incremental processing of appended input (`--state`)
+
+use std::cmp::min;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use memmap2::MmapOptions;
+use serde::{Deserialize, Serialize};
+use serde_json::from_str;
+use crate::{stable_hash_bytes, Aggregates};
+
+// Bytes hashed at the start and at the end of the processed prefix to recognise the same file
+const FINGERPRINT_BYTES: u64 = 64 * 1024;
+
+// Identifies the first `len` bytes of the input, so that appending to the file keeps it
+// valid while truncating or rewriting the processed part does not
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub(crate) struct PrefixFingerprint {
+    pub(crate) len: u64,
+    head_hash: u64,
+    tail_hash: u64,
+}
+
+impl PrefixFingerprint {
+    fn of(mmap: &[u8], len: u64) -> Self {
+        let head_end = min(len, FINGERPRINT_BYTES) as usize;
+        let tail_start = len.saturating_sub(FINGERPRINT_BYTES) as usize;
+        PrefixFingerprint {
+            len,
+            head_hash: stable_hash_bytes(&mmap[..head_end]),
+            tail_hash: stable_hash_bytes(&mmap[tail_start..len as usize]),
+        }
+    }
+}
+
+// First line of a state file; the second line holds the merged `Aggregates`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub(crate) struct StateHeader {
+    input: String,
+    pub(crate) processed: PrefixFingerprint,
+    lines_processed: usize,
+    pub(crate) runs: usize,
+}
+
+// Where the previous run stopped and where this one will, read on rank 0 and broadcast so
+// that every rank scans against the same view of a file that may still be growing
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub(crate) struct IncrementalStart {
+    // Header of a state that still matches the input; None when starting over
+    pub(crate) previous: Option<StateHeader>,
+    // Byte range to scan in this run
+    pub(crate) range: (u64, u64),
+    // The input up to its last complete line, which is what the scan covers once it is done
+    pub(crate) processed: PrefixFingerprint,
+    // Why a previous state was discarded
+    pub(crate) discarded: Option<String>,
+}
+
+// Check the state file against the input and find the appended bytes to scan. Only complete
+// lines are taken, so a line that is still being written is left for the next run.
+pub(crate) fn start_incremental(state_file: &Path, input_file: &str) -> IncrementalStart {
+    let file = File::open(input_file).expect("Failed to open input file");
+    let mmap = unsafe { MmapOptions::new().map(&file).expect("Failed to map file") };
+    let complete_len = mmap.iter().rposition(|&b| b == b'\n').map_or(0, |pos| pos as u64 + 1);
+    
+    let header: Option<StateHeader> = File::open(state_file).ok().and_then(|file| {
+        let mut line = String::new();
+        io::BufRead::read_line(&mut io::BufReader::new(file), &mut line).ok()?;
+        from_str(&line).ok()
+    });
+    let mut discarded = None;
+    let previous = header.filter(|header| {
+        let processed = header.processed;
+        if header.input != input_file {
+            discarded = Some("was written for a different input file".to_string());
+        } else if processed.len > mmap.len() as u64 {
+            discarded = Some("covers more bytes than the input has; the input was truncated".to_string());
+        } else if PrefixFingerprint::of(&mmap, processed.len) != processed {
+            discarded = Some("does not match the start of the input; the input was rewritten".to_string());
+        }
+        discarded.is_none()
+    });
+    
+    // Scanned ranges hold the lines starting after their start, so start on the newline that
+    // ends the last processed line; ending on the last newline leaves out a partial last line
+    let start = previous.as_ref().map_or(0, |header| header.processed.len.saturating_sub(1));
+    let end = complete_len.saturating_sub(1).max(start);
+    IncrementalStart {
+        previous,
+        range: (start, end),
+        processed: PrefixFingerprint::of(&mmap, complete_len),
+        discarded,
+    }
+}
+
+// Aggregates of the previous run, read on rank 0
+pub(crate) fn read_state_aggregates(state_file: &Path) -> Aggregates {
+    let file = File::open(state_file).expect("Failed to open state file");
+    let line = io::BufRead::lines(io::BufReader::new(file))
+        .nth(1)
+        .and_then(Result::ok)
+        .expect("State file has no aggregates");
+    from_str(&line).unwrap_or_else(|error| panic!("Failed to parse state file {}: {}", state_file.display(), error))
+}
+
+// Atomically replace the state file with the merged aggregates of every run so far, recording
+// the prefix this run was started to scan rather than whatever the input has grown to since
+pub(crate) fn write_state(
+    state_file: &Path,
+    input_file: &str,
+    incremental: &IncrementalStart,
+    lines_processed: usize,
+    aggregates: &Aggregates,
+) {
+    let previous = incremental.previous.as_ref();
+    let header = StateHeader {
+        input: input_file.to_string(),
+        processed: incremental.processed,
+        lines_processed: previous.map_or(0, |previous| previous.lines_processed) + lines_processed,
+        runs: previous.map_or(0, |previous| previous.runs) + 1,
+    };
+    
+    let partial_path = state_file.with_extension("partial");
+    let file = File::create(&partial_path).expect("Failed to create state file");
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &header).expect("Failed to write state file");
+    writeln!(writer).expect("Failed to write state file");
+    serde_json::to_writer(&mut writer, aggregates).expect("Failed to write state file");
+    writeln!(writer).expect("Failed to write state file");
+    writer.into_inner().expect("Failed to write state file").sync_all().expect("Failed to sync state file");
+    fs::rename(&partial_path, state_file).expect("Failed to move state file into place");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::for_each_chunk_line;
+    use crate::tests::scratch_dir;
+    
+    fn scan(input: &Path, range: (u64, u64)) -> Vec<String> {
+        let mut lines = Vec::new();
+        for_each_chunk_line(input.to_str().unwrap(), range.0, range.1, 64, |_, line| lines.push(line.to_string()));
+        lines
+    }
+    
+    #[test]
+    fn later_runs_scan_only_appended_complete_lines() {
+        let dir = scratch_dir("incremental");
+        let input = dir.join("input.ndjson");
+        let state = dir.join("state.json");
+        let name = input.to_str().unwrap();
+        let line = |n: usize| format!("{{\"n\":{}}}", n);
+        fs::write(&input, format!("{}\n{}\n{{\"n\":", line(0), line(1))).unwrap();
+        
+        // The first run leaves the line still being written for later
+        let first = start_incremental(&state, name);
+        assert!(first.previous.is_none() && first.discarded.is_none());
+        assert_eq!(scan(&input, first.range), [line(0), line(1)]);
+        write_state(&state, name, &first, 2, &Aggregates::default());
+        
+        // Lines appended after the scan started are not recorded as processed
+        fs::write(&input, format!("{}\n{}\n{}\n{}\n", line(0), line(1), line(2), line(3))).unwrap();
+        let second = start_incremental(&state, name);
+        assert_eq!(second.previous.as_ref().map(|header| header.processed), Some(first.processed));
+        assert_eq!(scan(&input, second.range), [line(2), line(3)]);
+        write_state(&state, name, &second, 2, &Aggregates::default());
+        
+        // Nothing new, nothing to scan
+        let third = start_incremental(&state, name);
+        assert_eq!(third.previous.as_ref().map(|header| header.runs), Some(2));
+        assert!(scan(&input, third.range).is_empty());
+        
+        // A rewritten start and a truncation both start over from the beginning
+        fs::write(&input, format!("{}\n{}\n{}\n{}\n", line(9), line(1), line(2), line(3))).unwrap();
+        let rewritten = start_incremental(&state, name);
+        assert!(rewritten.previous.is_none() && rewritten.discarded.is_some());
+        assert_eq!(scan(&input, rewritten.range).len(), 4);
+        fs::write(&input, format!("{}\n", line(0))).unwrap();
+        let truncated = start_incremental(&state, name);
+        assert!(truncated.previous.is_none() && truncated.discarded.is_some());
+        assert_eq!(scan(&input, truncated.range), [line(0)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! Per-language sentiment breakdown
+
+use std::collections::HashMap;
+use std::path::Path;
+use crate::{hour_ranking_lines, print_ranking, signed_score, top_n_by_value, write_lines, write_ranking, SentimentStats, SEPARATOR};
+
+fn languages_by_volume(language_stats: &HashMap<String, SentimentStats>) -> Vec<(&String, &SentimentStats)> {
+    let mut languages: Vec<_> = language_stats.iter().collect();
+    languages.sort_by(|a, b| b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(b.0)));
+    languages
+}
+
+pub(crate) fn dump_language_sentiment(language_stats: &HashMap<String, SentimentStats>, output_dir: &Path) {
+    let lines: Vec<String> = languages_by_volume(language_stats)
+        .into_iter()
+        .enumerate()
+        .map(|(i, (language, stats))| {
+            format!(
+                "{}. {} with {} posts, total sentiment {:+} and mean sentiment {:+.4}",
+                i + 1, language, stats.count, stats.sum, stats.mean()
+            )
+        })
+        .collect();
+    print_ranking("Sentiment by Language", &lines);
+    write_ranking("Sentiment by Language", "language_sentiment.txt", &lines, output_dir);
+}
+
+pub(crate) fn dump_language_hours(
+    language_stats: &HashMap<String, SentimentStats>,
+    language_hour_sentiment: &HashMap<String, HashMap<String, f64>>,
+    top_n: usize,
+    output_dir: &Path,
+) {
+    // One happiest and one saddest ranking per language, separated by blank lines
+    let mut lines = Vec::new();
+    for (language, _) in languages_by_volume(language_stats) {
+        let Some(hour_sentiment) = language_hour_sentiment.get(language) else {
+            continue;
+        };
+        for (title, largest) in [("Top Happiest Hours", true), ("Top Saddest Hours", false)] {
+            lines.push(format!("{} ({})", title, language));
+            lines.push(SEPARATOR.to_string());
+            lines.extend(hour_ranking_lines(&top_n_by_value(hour_sentiment, top_n, largest), signed_score));
+            lines.push(String::new());
+        }
+    }
+    write_lines("language_hours.txt", &lines, output_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{aggregate, exported_status};
+    use crate::{parse_list_option, ProcessingOptions, SentimentStats, UNKNOWN_LANGUAGE};
+    use serde_json::json;
+    
+    #[test]
+    fn language_breakdown_and_filter() {
+        let lines = [
+            exported_status(json!({"language": "EN", "sentiment": 0.5})),
+            exported_status(json!({"language": "de", "sentiment": -0.25})),
+            exported_status(json!({"language": "de", "sentiment": -0.75, "createdAt": "2025-01-30T12:10:00.000Z"})),
+            exported_status(json!({"language": null, "sentiment": 0.1})),
+        ];
+        let (all, _) = aggregate(&lines, &ProcessingOptions::default());
+        assert_eq!(all.language_stats["en"], SentimentStats { count: 1, sum: 0.5 });
+        assert_eq!(all.language_stats["de"], SentimentStats { count: 2, sum: -1.0 });
+        assert_eq!(all.language_stats[UNKNOWN_LANGUAGE].count, 1);
+        assert_eq!(all.language_hour_sentiment["de"]["2025-01-30 12"], -0.75);
+        
+        let options = ProcessingOptions { languages: Some(parse_list_option("de, DE")), ..Default::default() };
+        let (german, _) = aggregate(&lines, &options);
+        assert_eq!(german.language_stats.keys().collect::<Vec<_>>(), ["de"]);
+        assert_eq!(german.hour_count.values().sum::<usize>(), 2);
+        assert_eq!(german.language_filtered, 2);
+        assert_eq!(german.user_sentiment["109491602528257114"].1, -1.0);
+    }
+}
@@ -0,0 +1,413 @@
+//! Lexicon-based sentiment scoring for statuses without a usable `sentiment` field
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::{print_ranking, write_ranking};
+
+// Where the sentiment of a status comes from
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum SentimentSource {
+    // The precomputed `sentiment` field; statuses without it are skipped
+    #[default]
+    Field,
+    // Always score the status text with the lexicon
+    Lexicon,
+    // The field when present, the lexicon otherwise
+    Both,
+}
+
+impl SentimentSource {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "field" => Some(SentimentSource::Field),
+            "lexicon" => Some(SentimentSource::Lexicon),
+            "both" => Some(SentimentSource::Both),
+            _ => None,
+        }
+    }
+}
+
+// Computes a sentiment score in [-1, 1] from plain text
+pub(crate) trait SentimentScorer: std::fmt::Debug + Send + Sync {
+    fn score(&self, text: &str) -> f64;
+}
+
+// AFINN-style word valences (-5 to +5) used when no --lexicon file is given
+const BUNDLED_LEXICON: &str = "\
+abandon	-2
+abuse	-3
+accident	-2
+admire	3
+adore	3
+afraid	-2
+agree	1
+alarming	-2
+amazing	4
+angry	-3
+annoyed	-2
+annoying	-2
+anxious	-2
+appreciate	2
+awesome	4
+awful	-3
+bad	-3
+beautiful	3
+best	3
+better	2
+bitter	-2
+blessed	2
+boring	-3
+brave	2
+brilliant	4
+broken	-1
+calm	2
+care	2
+celebrate	3
+chaos	-2
+cheer	2
+clean	2
+collapse	-2
+comfortable	2
+confused	-2
+congrats	2
+congratulations	2
+cool	1
+corrupt	-3
+crash	-2
+crazy	-2
+crisis	-3
+cruel	-3
+cry	-1
+cute	2
+damage	-3
+danger	-2
+dead	-3
+death	-2
+delight	3
+depressed	-2
+despair	-3
+destroy	-3
+disappointed	-2
+disaster	-2
+disgusting	-3
+dislike	-2
+dreadful	-3
+easy	1
+enjoy	2
+evil	-3
+excellent	3
+excited	3
+exciting	3
+fail	-2
+failure	-2
+fair	2
+fake	-3
+fantastic	4
+fear	-2
+fine	2
+fortunate	2
+fraud	-4
+free	1
+fresh	1
+friendly	2
+fun	4
+funny	4
+glad	3
+good	3
+gorgeous	3
+grateful	3
+great	3
+grief	-2
+guilty	-3
+happy	3
+harm	-2
+hate	-3
+hateful	-3
+healthy	2
+help	2
+hero	2
+hope	2
+hopeful	2
+horrible	-3
+hurt	-2
+ill	-2
+impressive	3
+injustice	-2
+insane	-2
+interesting	2
+joy	3
+kill	-3
+kind	2
+laugh	1
+liar	-3
+like	2
+lonely	-2
+lose	-3
+loss	-3
+lost	-3
+love	3
+lovely	3
+lucky	3
+mad	-3
+magnificent	3
+mess	-2
+miserable	-3
+miss	-2
+mistake	-2
+nice	3
+outrage	-3
+outstanding	5
+pain	-2
+panic	-3
+peace	2
+perfect	3
+pleasant	3
+pleased	3
+poor	-2
+positive	2
+pretty	1
+proud	2
+problem	-2
+protect	1
+racist	-3
+rage	-2
+relief	1
+relieved	2
+remarkable	2
+sad	-2
+safe	1
+scandal	-3
+scared	-2
+shame	-2
+shock	-2
+sick	-2
+smile	2
+sorry	-1
+splendid	3
+strong	2
+stupid	-2
+succeed	3
+success	2
+suffer	-2
+support	2
+terrible	-3
+terrific	4
+thank	2
+thanks	2
+threat	-2
+tired	-2
+tragedy	-2
+tragic	-2
+trouble	-2
+trust	1
+ugly	-3
+unfair	-2
+unhappy	-2
+upset	-2
+useless	-2
+violence	-3
+war	-2
+warm	1
+welcome	2
+win	4
+wonderful	4
+worried	-3
+worry	-3
+worse	-3
+worst	-3
+wow	4
+wrong	-2
+yay	3
+";
+
+const NEGATIONS: &[&str] = &[
+    "not", "no", "never", "none", "nobody", "nothing", "neither", "nor", "nowhere", "cannot", "without",
+];
+const INTENSIFIERS: &[(&str, f64)] = &[
+    ("very", 1.3), ("really", 1.3), ("extremely", 1.5), ("so", 1.2), ("totally", 1.3),
+    ("absolutely", 1.4), ("incredibly", 1.4), ("super", 1.3), ("most", 1.2),
+    ("slightly", 0.6), ("somewhat", 0.7), ("barely", 0.5), ("kinda", 0.7), ("little", 0.8),
+];
+
+// Words after a negation whose valence is flipped
+const NEGATION_SCOPE: usize = 3;
+// VADER's damping of negated valences
+const NEGATION_FACTOR: f64 = -0.74;
+// VADER's normalisation constant for the compound score
+const NORMALIZATION_ALPHA: f64 = 15.0;
+
+#[derive(Debug, Clone)]
+pub(crate) struct LexiconScorer {
+    lexicon: HashMap<String, f64>,
+}
+
+impl LexiconScorer {
+    pub(crate) fn bundled() -> Self {
+        Self::parse(BUNDLED_LEXICON)
+    }
+    
+    // Tab-separated `term<TAB>score` lines as in AFINN; `#` starts a comment
+    pub(crate) fn from_file(path: &str) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+    
+    pub(crate) fn parse(contents: &str) -> Self {
+        let lexicon = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (term, score) = line.rsplit_once('\t').or_else(|| line.rsplit_once(' '))?;
+                Some((term.trim().to_lowercase(), score.trim().parse().ok()?))
+            })
+            .collect();
+        LexiconScorer { lexicon }
+    }
+}
+
+impl SentimentScorer for LexiconScorer {
+    fn score(&self, text: &str) -> f64 {
+        let lowered = text.to_lowercase();
+        let tokens: Vec<&str> = lowered
+            .split(|c: char| !(c.is_alphanumeric() || c == '\''))
+            .map(|token| token.trim_matches('\''))
+            .filter(|token| !token.is_empty())
+            .collect();
+        
+        let mut total = 0.0;
+        let mut negated_for = 0;
+        let mut intensity = 1.0;
+        for token in tokens {
+            if NEGATIONS.contains(&token) || token.ends_with("n't") {
+                negated_for = NEGATION_SCOPE;
+                continue;
+            }
+            if let Some((_, factor)) = INTENSIFIERS.iter().find(|(word, _)| *word == token) {
+                intensity *= factor;
+                continue;
+            }
+            
+            if let Some(valence) = self.lexicon.get(token) {
+                let mut valence = valence * intensity;
+                if negated_for > 0 {
+                    valence *= NEGATION_FACTOR;
+                }
+                total += valence;
+            }
+            intensity = 1.0;
+            negated_for = negated_for.saturating_sub(1);
+        }
+        
+        total / (total * total + NORMALIZATION_ALPHA).sqrt()
+    }
+}
+
+// Running sums for comparing the precomputed field with the lexicon score
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy)]
+pub(crate) struct SentimentComparison {
+    count: usize,
+    sum_field: f64,
+    sum_lexicon: f64,
+    sum_field_sq: f64,
+    sum_lexicon_sq: f64,
+    sum_product: f64,
+    sign_agreements: usize,
+}
+
+impl SentimentComparison {
+    pub(crate) fn add(&mut self, field: f64, lexicon: f64) {
+        self.count += 1;
+        self.sum_field += field;
+        self.sum_lexicon += lexicon;
+        self.sum_field_sq += field * field;
+        self.sum_lexicon_sq += lexicon * lexicon;
+        self.sum_product += field * lexicon;
+        if field.signum() == lexicon.signum() || (field == 0.0 && lexicon == 0.0) {
+            self.sign_agreements += 1;
+        }
+    }
+    
+    pub(crate) fn merge(&mut self, other: &SentimentComparison) {
+        self.count += other.count;
+        self.sum_field += other.sum_field;
+        self.sum_lexicon += other.sum_lexicon;
+        self.sum_field_sq += other.sum_field_sq;
+        self.sum_lexicon_sq += other.sum_lexicon_sq;
+        self.sum_product += other.sum_product;
+        self.sign_agreements += other.sign_agreements;
+    }
+    
+    fn correlation(&self) -> Option<f64> {
+        let n = self.count as f64;
+        let covariance = n * self.sum_product - self.sum_field * self.sum_lexicon;
+        let field_variance = n * self.sum_field_sq - self.sum_field * self.sum_field;
+        let lexicon_variance = n * self.sum_lexicon_sq - self.sum_lexicon * self.sum_lexicon;
+        if field_variance <= 0.0 || lexicon_variance <= 0.0 {
+            None
+        } else {
+            Some(covariance / (field_variance * lexicon_variance).sqrt())
+        }
+    }
+}
+
+pub(crate) fn dump_sentiment_comparison(comparison: &SentimentComparison, output_dir: &Path) {
+    let mut output = Vec::new();
+    if comparison.count == 0 {
+        output.push("No statuses carried both a sentiment field and content".to_string());
+    } else {
+        let n = comparison.count as f64;
+        output.push(format!("Statuses compared: {}", comparison.count));
+        output.push(format!("Mean field sentiment: {:+.4}", comparison.sum_field / n));
+        output.push(format!("Mean lexicon sentiment: {:+.4}", comparison.sum_lexicon / n));
+        output.push(match comparison.correlation() {
+            Some(r) => format!("Pearson correlation: {:+.4}", r),
+            None => "Pearson correlation: n/a".to_string(),
+        });
+        output.push(format!(
+            "Sign agreement: {:.2}%",
+            100.0 * comparison.sign_agreements as f64 / n
+        ));
+    }
+    print_ranking("Sentiment Field vs Lexicon", &output);
+    write_ranking("Sentiment Field vs Lexicon", "sentiment_comparison.txt", &output, output_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn score(text: &str) -> f64 {
+        LexiconScorer::bundled().score(text)
+    }
+    
+    #[test]
+    fn lexicon_scores_are_signed_and_bounded() {
+        assert!(score("What a wonderful day, thanks!") > 0.0);
+        assert!(score("This is terrible and stupid") < 0.0);
+        assert_eq!(score("The train leaves at noon"), 0.0);
+        assert!(score(&"wonderful ".repeat(50)) < 1.0);
+    }
+    
+    #[test]
+    fn negation_flips_and_dampens_the_next_words() {
+        assert!(score("not good") < 0.0);
+        assert!(score("not good").abs() < score("good"));
+        assert!(score("I don't like it") < 0.0);
+        // The scope ends NEGATION_SCOPE words after the negation
+        assert!(score("not at all in any way good") > 0.0);
+    }
+    
+    #[test]
+    fn intensifiers_scale_only_the_following_word() {
+        assert!(score("very good") > score("good"));
+        assert!(score("slightly good") < score("good"));
+        assert_eq!(score("very"), 0.0);
+        // "super" is a booster rather than a scored word
+        assert_eq!(score("super"), 0.0);
+        assert!(score("super good") > score("good"));
+        assert_eq!(score("very good day good"), score("good day very good"));
+    }
+}
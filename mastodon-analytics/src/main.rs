@@ -1,18 +1,66 @@
-use chrono::{DateTime, TimeZone, Utc};
-use mpi::{self, collective::Root, environment::Universe, traits::*};
-use memmap2::{Mmap, MmapOptions};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Duration, NaiveDateTime, Timelike, Utc};
+use mpi::{self, collective::SystemOperation, traits::*};
+use memmap2::MmapOptions;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{from_str, Value};
+use std::borrow::Cow;
 use std::cmp::{min, Ordering};
-use std::collections::{BinaryHeap, HashMap};
-use std::fs::{self, File, OpenOptions};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
 use std::io::{self, BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Instant;
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use std::os::unix::fs::MetadataExt;
 
+mod anomalies;
+mod categories;
+mod checkpoint;
+mod dedup;
+mod engagement;
+mod filter;
+mod graph;
+mod group_by;
+mod groups;
+mod incremental;
+mod language;
+mod lexicon;
+mod pagerank;
+mod reblogs;
+mod sketches;
+mod spill;
+mod text;
+mod time_range;
+mod threads;
+mod trends;
+mod watch;
+mod words;
+
+use anomalies::{detect_hour_anomalies, dump_hour_anomalies};
+use categories::{dump_category_sentiment, status_categories};
+use checkpoint::{remove_checkpoints, segment_owner, start_checkpointing, CheckpointOptions, ScanProgress, ScanSegment, ScanState};
+use dedup::find_duplicate_offsets;
+use engagement::{dump_engaged_posts, dump_engagement_by_sentiment, dump_hour_engagement};
+use filter::FilterExpr;
+use graph::{dump_graph_edge_list, dump_graph_graphml, partition_graph};
+use group_by::{add_to_groups, dump_grouped_table, group_by_name, parse_group_by, GroupDimension};
+use groups::{dump_group_hourly, dump_group_ranking, dump_group_table, group_mean_sentiment};
+use incremental::{read_state_aggregates, start_incremental, write_state, IncrementalStart};
+use language::{dump_language_hours, dump_language_sentiment};
+use lexicon::{dump_sentiment_comparison, LexiconScorer, SentimentComparison, SentimentScorer, SentimentSource};
+use pagerank::{distributed_pagerank, dump_influential_users, dump_user_ranking, PageRankParams};
+use reblogs::{dump_most_reblogged_authors, ReblogMode};
+use sketches::{dump_approx_users, dump_distinct_users, dump_sentiment_distribution, ApproxUserParams, ApproxUserTotal, ApproxUsers, HyperLogLog, TDigest};
+use spill::{finish_external_aggregation, SpillState};
+use text::{dump_extracted_text, extract_text};
+use threads::{build_threads, collect_conversation_nodes, count_unresolved_parents, dump_thread_ranking, dump_thread_table, ThreadMetrics};
+use time_range::{locate_time_range, parse_time_bound};
+use trends::{detect_trending_hashtags, dump_trending_hashtags};
+use watch::{run_watch, WatchOptions};
+use words::{bundled_stopwords, count_terms, dump_term_ranking, dump_term_table, term_associations, top_n_associations};
+
 // -----------------------------------
 // Config module - from config.py
 // -----------------------------------
@@ -20,12 +68,6 @@ struct Config {
     data_dir: String,
     output_dir: String,
     log_dir: String,
-    preprocessed_data_prefix: String,
-    input_file_pattern: String,
-    default_input_file: String,
-    cloud_storage_bucket: Option<String>,
-    cloud_input_path: Option<String>,
-    cloud_output_path: Option<String>,
 }
 
 impl Default for Config {
@@ -34,51 +76,11 @@ impl Default for Config {
             data_dir: "/Users/manishghoshal/Desktop/UniMelb/Semester 1/CCC/CCC_A1/data/mastodon-16m.ndjson".to_string(),
             output_dir: "/Users/manishghoshal/Desktop/UniMelb/Semester 1/CCC/CCC_A1/output".to_string(),
             log_dir: "./logs".to_string(),
-            preprocessed_data_prefix: "preprocessed_".to_string(),
-            input_file_pattern: "*.ndjson".to_string(),
-            default_input_file: "mastodon_data.ndjson".to_string(),
-            cloud_storage_bucket: None,
-            cloud_input_path: None,
-            cloud_output_path: None,
         }
     }
 }
 
 impl Config {
-    fn load(env: Option<&str>, custom_config: Option<HashMap<String, String>>) -> Self {
-        let mut config = Config::default();
-        
-        if let Some(env_name) = env {
-            match env_name {
-                "development" => {
-                    config.data_dir = "./dev_data".to_string();
-                    config.output_dir = "./dev_output".to_string();
-                },
-                "production" => {
-                    config.data_dir = "/opt/mastodon/data".to_string();
-                    config.output_dir = "/opt/mastodon/output".to_string();
-                },
-                "testing" => {
-                    config.data_dir = "./test_data".to_string();
-                    config.output_dir = "./test_output".to_string();
-                },
-                _ => {}
-            }
-        }
-        
-        if let Some(custom) = custom_config {
-            if let Some(data_dir) = custom.get("data_dir") {
-                config.data_dir = data_dir.clone();
-            }
-            if let Some(output_dir) = custom.get("output_dir") {
-                config.output_dir = output_dir.clone();
-            }
-            // Add other fields as needed
-        }
-        
-        config
-    }
-    
     fn resolve_path(&self, base_dir: &str, filename: &str) -> PathBuf {
         let dir_path = match base_dir {
             "data_dir" => &self.data_dir,
@@ -109,14 +111,47 @@ struct MastodonData {
     user_id: Option<String>,
     username: Option<String>,
     sentiment: Option<f64>,
+    language: Option<String>,
+    tags: Vec<String>,
+    instance: Option<String>,
+    acct: Option<String>,
+    mentions: Vec<(String, String)>,
+    in_reply_to_account_id: Option<String>,
+    status_id: Option<String>,
+    in_reply_to_id: Option<String>,
+    uri: Option<String>,
+    in_reply_to_uri: Option<String>,
+    reply_uri_exported: bool,
+    content: Option<String>,
+    emojis: Option<Vec<String>>,
+    url: Option<String>,
+    favourites_count: u64,
+    reblogs_count: u64,
+    replies_count: u64,
+    reblog: Option<Box<MastodonData>>,
+    bot: bool,
+    group: bool,
+    sensitive: bool,
+    visibility: Option<String>,
+    has_spoiler: bool,
 }
 
 impl MastodonData {
     fn from_json_str(json_str: &str) -> Result<Self, serde_json::Error> {
         let data: Value = from_str(json_str)?;
         
+        // Elasticsearch exports wrap the status in a `doc` object
+        let data = data.get("doc").unwrap_or(&data);
+        
+        Ok(Self::from_value(data))
+    }
+    
+    fn from_value(data: &Value) -> Self {
         // Extract fields with proper error handling
-        let created_at = data.get("created_at").and_then(|v| v.as_str()).map(String::from);
+        let created_at = data.get("created_at")
+            .or_else(|| data.get("createdAt"))
+            .and_then(|v| v.as_str())
+            .map(String::from);
         let user_id = data.get("account").and_then(|a| a.get("id")).and_then(|v| v.as_str()).map(String::from);
         let username = data.get("account").and_then(|a| a.get("username")).and_then(|v| v.as_str()).map(String::from);
         
@@ -124,15 +159,172 @@ impl MastodonData {
         // In the original, this might be calculated rather than directly present
         let sentiment = data.get("sentiment").and_then(|v| v.as_f64());
         
-        Ok(MastodonData {
+        let language = data.get("language").and_then(|v| v.as_str()).map(|v| v.to_lowercase());
+        
+        // Hashtag names, lower-cased and deduplicated within the status
+        let mut tags: Vec<String> = data.get("tags")
+            .and_then(|v| v.as_array())
+            .map(|tags| {
+                tags.iter()
+                    .filter_map(|tag| tag.get("name").and_then(|v| v.as_str()))
+                    .map(|name| name.to_lowercase())
+                    .collect()
+            })
+            .unwrap_or_default();
+        tags.sort();
+        tags.dedup();
+        
+        let acct = data.get("account").and_then(|a| a.get("acct")).and_then(|v| v.as_str()).map(String::from);
+        
+        // Home instance from `acct` (user@domain for remote accounts), else from the profile URL
+        let instance = acct
+            .as_deref()
+            .and_then(|acct| acct.split_once('@').map(|(_, domain)| domain.to_string()))
+            .or_else(|| {
+                data.get("account")
+                    .and_then(|a| a.get("url"))
+                    .and_then(|v| v.as_str())
+                    .and_then(url_host)
+            })
+            .map(|domain| domain.to_lowercase());
+        
+        // Mentioned accounts as (id, acct) pairs
+        let mentions = data.get("mentions")
+            .and_then(|v| v.as_array())
+            .map(|mentions| {
+                mentions.iter()
+                    .filter_map(|mention| {
+                        let id = mention.get("id").and_then(|v| v.as_str())?;
+                        let acct = mention.get("acct").and_then(|v| v.as_str()).unwrap_or(id);
+                        Some((id.to_string(), acct.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let in_reply_to_account_id = data.get("inReplyToAccountId")
+            .or_else(|| data.get("in_reply_to_account_id"))
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        
+        // Some exports also link replies by URI, which unlike `id` is the same on every instance
+        let uri = data.get("uri").and_then(|v| v.as_str()).map(String::from);
+        let url = data.get("url").and_then(|v| v.as_str()).map(String::from);
+        
+        // Exports such as the sample one carry no `id`; for statuses of the harvested instance the
+        // last path segment of the URL (else the URI) is the local id that `inReplyToId` refers to
+        let status_id = data.get("id")
+            .and_then(|v| v.as_str())
+            .map(String::from)
+            .or_else(|| url.as_deref().or(uri.as_deref()).and_then(last_path_segment));
+        let in_reply_to_id = data.get("inReplyToId")
+            .or_else(|| data.get("in_reply_to_id"))
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        
+        let reply_uri_field = data.get("inReplyToUri").or_else(|| data.get("in_reply_to_uri"));
+        let reply_uri_exported = reply_uri_field.is_some();
+        let in_reply_to_uri = reply_uri_field.and_then(|v| v.as_str()).map(String::from);
+        
+        let content = data.get("content").and_then(|v| v.as_str()).map(String::from);
+        // Shortcodes of the custom emoji used in the content
+        let emojis = data.get("emojis").and_then(|v| v.as_array()).map(|emojis| {
+            emojis
+                .iter()
+                .filter_map(|emoji| emoji.get("shortcode").and_then(|v| v.as_str()).map(String::from))
+                .collect()
+        });
+        
+        // Engagement counters; missing counters count as zero
+        let counter = |camel: &str, snake: &str| {
+            data.get(camel).or_else(|| data.get(snake)).and_then(|v| v.as_u64()).unwrap_or(0)
+        };
+        let favourites_count = counter("favouritesCount", "favourites_count");
+        let reblogs_count = counter("reblogsCount", "reblogs_count");
+        let replies_count = counter("repliesCount", "replies_count");
+        
+        let account_flag = |field: &str| {
+            data.get("account").and_then(|a| a.get(field)).and_then(|v| v.as_bool()).unwrap_or(false)
+        };
+        let bot = account_flag("bot");
+        let group = account_flag("group");
+        let sensitive = data.get("sensitive").and_then(|v| v.as_bool()).unwrap_or(false);
+        let visibility = data.get("visibility").and_then(|v| v.as_str()).map(|v| v.to_lowercase());
+        let has_spoiler = data.get("spoilerText")
+            .or_else(|| data.get("spoiler_text"))
+            .and_then(|v| v.as_str())
+            .is_some_and(|text| !text.trim().is_empty());
+        
+        // The reblogged status, for statuses that are boosts of another one
+        let reblog = data.get("reblog")
+            .filter(|v| v.is_object())
+            .map(|v| Box::new(Self::from_value(v)));
+        
+        MastodonData {
             created_at,
             user_id,
             username,
             sentiment,
-        })
+            language,
+            tags,
+            instance,
+            acct,
+            mentions,
+            in_reply_to_account_id,
+            status_id,
+            in_reply_to_id,
+            uri,
+            in_reply_to_uri,
+            reply_uri_exported,
+            content,
+            emojis,
+            url,
+            favourites_count,
+            reblogs_count,
+            replies_count,
+            reblog,
+            bot,
+            group,
+            sensitive,
+            visibility,
+            has_spoiler,
+        }
+    }
+    
+    // Key of the status and of the status it replies to, both from the same id space: URIs when
+    // the export carries `inReplyToUri`, the instance-local `id`/`inReplyToId` otherwise
+    fn reply_link(&self) -> Option<(String, Option<String>)> {
+        if self.reply_uri_exported {
+            self.uri.clone().map(|uri| (uri, self.in_reply_to_uri.clone()))
+        } else {
+            self.status_id.clone().map(|id| (id, self.in_reply_to_id.clone()))
+        }
+    }
+    
+    fn is_reply(&self) -> bool {
+        self.in_reply_to_id.is_some() || self.in_reply_to_uri.is_some()
+    }
+}
+
+// Host part of an http(s) URL, without port or credentials
+fn url_host(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map(|(_, rest)| rest)?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?.split(':').next()?;
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_string())
     }
 }
 
+// Last non-empty path segment of an http(s) URL, e.g. the status id of
+// https://mastodon.social/@alice/113917174294487934
+fn last_path_segment(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map(|(_, rest)| rest)?;
+    let (_, path) = rest.split(['?', '#']).next()?.split_once('/')?;
+    path.rsplit('/').find(|segment| !segment.is_empty()).map(String::from)
+}
+
 // -----------------------------------
 // Utility functions - from util.py
 // -----------------------------------
@@ -147,18 +339,637 @@ fn preprocess_data(data: &str) -> Option<String> {
     }
 }
 
+// Language key used for statuses without a `language` field
+const UNKNOWN_LANGUAGE: &str = "und";
+// Visibility key used for statuses without a `visibility` field
+const UNKNOWN_VISIBILITY: &str = "unknown";
+// Number of entries in the top-N rankings
+const TOP_N: usize = 5;
+
+// Post count and summed sentiment for one aggregation key
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq)]
+struct SentimentStats {
+    count: usize,
+    sum: f64,
+}
+
+impl SentimentStats {
+    fn add(&mut self, sentiment: f64) {
+        self.count += 1;
+        self.sum += sentiment;
+    }
+    
+    fn merge(&mut self, other: &SentimentStats) {
+        self.count += other.count;
+        self.sum += other.sum;
+    }
+    
+    fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+}
+
+// Count, sum and range of the sentiment of one --group-by key
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+struct SentimentSummary {
+    stats: SentimentStats,
+    min: f64,
+    max: f64,
+}
+
+impl SentimentSummary {
+    fn new(sentiment: f64) -> Self {
+        SentimentSummary { stats: SentimentStats { count: 1, sum: sentiment }, min: sentiment, max: sentiment }
+    }
+    
+    fn add(&mut self, sentiment: f64) {
+        self.stats.add(sentiment);
+        self.min = self.min.min(sentiment);
+        self.max = self.max.max(sentiment);
+    }
+    
+    fn merge(&mut self, other: &SentimentSummary) {
+        self.stats.merge(&other.stats);
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+// Sentiment statistics together with the distinct users that contributed to them, when known
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+struct GroupStats {
+    stats: SentimentStats,
+    users: Option<HyperLogLog>,
+}
+
+impl GroupStats {
+    fn add(&mut self, user_id: Option<&str>, sentiment: f64) {
+        self.stats.add(sentiment);
+        if let Some(user_id) = user_id {
+            self.users.get_or_insert_with(HyperLogLog::default).insert(user_id);
+        }
+    }
+    
+    fn merge(&mut self, other: GroupStats) {
+        self.stats.merge(&other.stats);
+        if let Some(users) = other.users {
+            self.users.get_or_insert_with(HyperLogLog::default).merge(&users);
+        }
+    }
+}
+
+// Occurrences of a word or bigram, split by the sign of the status sentiment
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy)]
+struct TermCounts {
+    count: usize,
+    positive: usize,
+    negative: usize,
+    sentiment_sum: f64,
+}
+
+impl TermCounts {
+    fn add(&mut self, sentiment: f64) {
+        self.count += 1;
+        self.sentiment_sum += sentiment;
+        if sentiment > 0.0 {
+            self.positive += 1;
+        } else if sentiment < 0.0 {
+            self.negative += 1;
+        }
+    }
+    
+    fn merge(&mut self, other: &TermCounts) {
+        self.count += other.count;
+        self.positive += other.positive;
+        self.negative += other.negative;
+        self.sentiment_sum += other.sentiment_sum;
+    }
+    
+    fn mean_sentiment(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sentiment_sum / self.count as f64
+        }
+    }
+}
+
+// Number of equal-width sentiment bins over [-1, 1] used for the sentiment histograms
+const SENTIMENT_BINS: usize = 10;
+// Resolution of the sentiment histogram that the engagement deciles are cut from
+const ENGAGEMENT_HISTOGRAM_BINS: usize = 2000;
+
+// Summed engagement counters of a set of statuses
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy)]
+struct EngagementStats {
+    posts: usize,
+    favourites: u64,
+    reblogs: u64,
+    replies: u64,
+    sentiment_sum: f64,
+}
+
+impl EngagementStats {
+    fn add(&mut self, mastodon_data: &MastodonData, sentiment: f64) {
+        self.posts += 1;
+        self.favourites += mastodon_data.favourites_count;
+        self.reblogs += mastodon_data.reblogs_count;
+        self.replies += mastodon_data.replies_count;
+        self.sentiment_sum += sentiment;
+    }
+    
+    fn merge(&mut self, other: &EngagementStats) {
+        self.posts += other.posts;
+        self.favourites += other.favourites;
+        self.reblogs += other.reblogs;
+        self.replies += other.replies;
+        self.sentiment_sum += other.sentiment_sum;
+    }
+    
+    fn mean(&self, total: u64) -> f64 {
+        if self.posts == 0 {
+            0.0
+        } else {
+            total as f64 / self.posts as f64
+        }
+    }
+}
+
+// Add `other` bin by bin to a histogram that is allocated on first use
+fn merge_engagement_histograms(histogram: &mut Vec<EngagementStats>, other: &[EngagementStats]) {
+    if histogram.len() < other.len() {
+        histogram.resize(other.len(), EngagementStats::default());
+    }
+    for (merged, stats) in histogram.iter_mut().zip(other) {
+        merged.merge(stats);
+    }
+}
+
+// Sentiment of an hour weighted by 1 + favourites + reblogs + replies, so that statuses
+// nobody interacted with still count once
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy)]
+struct WeightedSentiment {
+    weight: f64,
+    weighted_sum: f64,
+}
+
+impl WeightedSentiment {
+    fn add(&mut self, sentiment: f64, weight: f64) {
+        self.weight += weight;
+        self.weighted_sum += sentiment * weight;
+    }
+    
+    fn merge(&mut self, other: &WeightedSentiment) {
+        self.weight += other.weight;
+        self.weighted_sum += other.weighted_sum;
+    }
+    
+    fn mean(&self) -> f64 {
+        if self.weight == 0.0 {
+            0.0
+        } else {
+            self.weighted_sum / self.weight
+        }
+    }
+}
+
+// A status kept for the most-engaged rankings
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct EngagedPost {
+    status_id: Option<String>,
+    url: Option<String>,
+    acct: Option<String>,
+    created_at: Option<String>,
+    sentiment: f64,
+    engagement: u64,
+    favourites: u64,
+    reblogs: u64,
+    replies: u64,
+}
+
+fn sentiment_bin(sentiment: f64) -> usize {
+    let position = (sentiment.clamp(-1.0, 1.0) + 1.0) / 2.0;
+    min((position * ENGAGEMENT_HISTOGRAM_BINS as f64) as usize, ENGAGEMENT_HISTOGRAM_BINS - 1)
+}
+
+fn sentiment_class(sentiment: f64) -> &'static str {
+    if sentiment > 0.0 {
+        "positive"
+    } else if sentiment < 0.0 {
+        "negative"
+    } else {
+        "neutral"
+    }
+}
+
+// Keep `posts` sorted by engagement (highest first) and at most `limit` long
+fn insert_engaged_post(posts: &mut Vec<EngagedPost>, post: EngagedPost, limit: usize) {
+    let index = posts.partition_point(|existing| existing.engagement >= post.engagement);
+    posts.insert(index, post);
+    posts.truncate(limit);
+}
+
+// Per-rank aggregates, merged on rank 0 after the scan
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+struct Aggregates {
+    hour_sentiment: HashMap<String, f64>,
+    hour_count: HashMap<String, usize>,
+    user_sentiment: HashMap<String, (String, f64)>,
+    language_stats: HashMap<String, SentimentStats>,
+    language_hour_sentiment: HashMap<String, HashMap<String, f64>>,
+    hashtag_stats: HashMap<String, GroupStats>,
+    hashtag_hour_stats: HashMap<String, HashMap<String, SentimentStats>>,
+    hashtag_hour_count: HashMap<String, HashMap<String, usize>>,
+    instance_stats: HashMap<String, GroupStats>,
+    instance_hour_stats: HashMap<String, HashMap<String, SentimentStats>>,
+    sentiment_comparison: SentimentComparison,
+    word_counts: HashMap<String, TermCounts>,
+    bigram_counts: HashMap<String, TermCounts>,
+    engagement_histogram: Vec<EngagementStats>,
+    hour_engagement: HashMap<String, WeightedSentiment>,
+    top_engaged: HashMap<String, Vec<EngagedPost>>,
+    duplicates_skipped: usize,
+    reblogs_skipped: usize,
+    reblog_stats: SentimentStats,
+    reblogged_authors: HashMap<String, GroupStats>,
+    category_stats: HashMap<String, HashMap<String, SentimentStats>>,
+    language_filtered: usize,
+    category_filtered: usize,
+    expression_filtered: usize,
+    time_filtered: usize,
+    unlinked_replies: usize,
+    grouped: HashMap<String, HashMap<String, SentimentSummary>>,
+    all_users: HyperLogLog,
+    hour_users: HashMap<String, HyperLogLog>,
+    day_users: HashMap<String, HyperLogLog>,
+    sentiment_digest: TDigest,
+    hour_digests: HashMap<String, TDigest>,
+    user_digests: HashMap<String, TDigest>,
+    approx_users: Option<ApproxUsers>,
+    spill_files: usize,
+}
+
+// Interactions from one account to another, summed over all statuses
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy)]
+struct EdgeStats {
+    mentions: usize,
+    replies: usize,
+    sentiment_sum: f64,
+}
+
+impl EdgeStats {
+    fn weight(&self) -> usize {
+        self.mentions + self.replies
+    }
+    
+    fn mean_sentiment(&self) -> f64 {
+        if self.weight() == 0 {
+            0.0
+        } else {
+            self.sentiment_sum / self.weight() as f64
+        }
+    }
+    
+    fn merge(&mut self, other: &EdgeStats) {
+        self.mentions += other.mentions;
+        self.replies += other.replies;
+        self.sentiment_sum += other.sentiment_sum;
+    }
+}
+
+// Directed user-interaction graph (source id -> target id -> edge), with account labels.
+// Unlike `Aggregates` it is never collected on one rank: after the scan every rank keeps
+// only the edges whose source account it owns (see `owner_rank`).
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+struct InteractionGraph {
+    edges: HashMap<String, HashMap<String, EdgeStats>>,
+    labels: HashMap<String, String>,
+}
+
+impl InteractionGraph {
+    fn add_interaction(&mut self, source: &str, target: &str, is_reply: bool, sentiment: f64) {
+        let edge = self.edges
+            .entry(source.to_string())
+            .or_default()
+            .entry(target.to_string())
+            .or_default();
+        if is_reply {
+            edge.replies += 1;
+        } else {
+            edge.mentions += 1;
+        }
+        edge.sentiment_sum += sentiment;
+    }
+    
+    fn add_label(&mut self, id: &str, label: &str) {
+        if !self.labels.contains_key(id) {
+            self.labels.insert(id.to_string(), label.to_string());
+        }
+    }
+    
+    fn merge(&mut self, other: InteractionGraph) {
+        for (source, targets) in other.edges {
+            let merged_targets = self.edges.entry(source).or_default();
+            for (target, edge) in targets {
+                merged_targets.entry(target).or_default().merge(&edge);
+            }
+        }
+        for (id, label) in other.labels {
+            self.labels.entry(id).or_insert(label);
+        }
+    }
+    
+    fn edge_count(&self) -> usize {
+        self.edges.values().map(|targets| targets.len()).sum()
+    }
+}
+
+// Minimal view of a status needed to reassemble reply threads
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct ThreadNode {
+    id: String,
+    parent: Option<String>,
+    user_id: Option<String>,
+    sentiment: f64,
+}
+
+// Per-rank state that is redistributed between ranks after the scan rather than gathered on rank 0
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct PartitionedData {
+    graph: InteractionGraph,
+    thread_nodes: Vec<ThreadNode>,
+}
+
+// Record-level options applied while processing each line
+#[derive(Debug, Default, Clone)]
+struct ProcessingOptions {
+    languages: Option<HashSet<String>>,
+    hashtag_watchlist: HashSet<String>,
+    build_graph: bool,
+    build_threads: bool,
+    sentiment_source: SentimentSource,
+    scorer: Option<Arc<dyn SentimentScorer>>,
+    reblog_mode: ReblogMode,
+    exclude_bots: bool,
+    exclude_sensitive: bool,
+    visibilities: Option<HashSet<String>>,
+    filter: Option<FilterExpr>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    group_by: Vec<Vec<GroupDimension>>,
+    count_words: bool,
+    quantiles: bool,
+    approx_users: Option<ApproxUserParams>,
+    engagement_top: usize,
+    stopwords: HashMap<String, HashSet<String>>,
+    // Set by --memory-limit: hashtag, instance and reblogged-author stats keep no distinct-user sketches
+    memory_bounded: bool,
+}
+
+fn parse_list_option(value: &str) -> HashSet<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().trim_start_matches('#').to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// Value parser for counts that must be at least 1
+fn parse_positive_count(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("expected a whole number of at least 1, got '{}'", value)),
+    }
+}
+
+fn parse_open_fraction(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction < 1.0 => Ok(fraction),
+        _ => Err(format!("expected a number strictly between 0 and 1, got '{}'", value)),
+    }
+}
+
+// User counted in the distinct users of a hashtag, instance or reblogged author
+fn group_user<'a>(mastodon_data: &'a MastodonData, options: &ProcessingOptions) -> Option<&'a str> {
+    mastodon_data.user_id.as_deref().filter(|_| !options.memory_bounded)
+}
+
+// Pick the sentiment of a status according to --sentiment-source, recording how the
+// precomputed field and the lexicon compare whenever both are available
+fn resolve_sentiment(
+    mastodon_data: &MastodonData,
+    text: Option<&str>,
+    aggregates: &mut Aggregates,
+    options: &ProcessingOptions,
+) -> Option<f64> {
+    let field = mastodon_data.sentiment;
+    let scorer = match (&options.scorer, options.sentiment_source) {
+        (Some(scorer), SentimentSource::Lexicon | SentimentSource::Both) => scorer,
+        _ => return field,
+    };
+    
+    let lexicon = text.map(|text| scorer.score(text));
+    if let (Some(field), Some(lexicon)) = (field, lexicon) {
+        aggregates.sentiment_comparison.add(field, lexicon);
+    }
+    match options.sentiment_source {
+        SentimentSource::Both => field.or(lexicon),
+        _ => lexicon,
+    }
+}
+
+// Plain text of the status when some stage needs it, extracted once for all of them
+fn status_text(mastodon_data: &MastodonData, needed: bool) -> Option<String> {
+    let content = mastodon_data.content.as_deref().filter(|_| needed)?;
+    Some(extract_text(content, mastodon_data.emojis.as_deref()).text)
+}
+
 fn processing_data(
     preprocessed_line: &str,
-    hour_sentiment_dict: &mut HashMap<String, f64>,
-    user_sentiment_dict: &mut HashMap<String, (String, f64)>,
+    aggregates: &mut Aggregates,
+    partitioned: &mut PartitionedData,
+    options: &ProcessingOptions,
 ) {
-    match MastodonData::from_json_str(preprocessed_line) {
-        Ok(mastodon_data) => {
+    match from_str::<Value>(preprocessed_line) {
+        Ok(record) => {
+            // Elasticsearch exports wrap the status in a `doc` object
+            let status = record.get("doc").unwrap_or(&record);
+            if options.filter.as_ref().is_some_and(|filter| !filter.matches(status)) {
+                aggregates.expression_filtered += 1;
+                return;
+            }
+            let mut mastodon_data = MastodonData::from_value(status);
+            
+            if options.from.is_some() || options.to.is_some() {
+                let time = mastodon_data.created_at.as_deref().and_then(status_time);
+                let in_range = time.is_some_and(|time| {
+                    options.from.is_none_or(|from| time >= from) && options.to.is_none_or(|to| time < to)
+                });
+                if !in_range {
+                    aggregates.time_filtered += 1;
+                    return;
+                }
+            }
+            
+            // The filters look at the status that is counted: for a boost counted as its original,
+            // that is the boosted status
+            let counted = match (&mastodon_data.reblog, options.reblog_mode) {
+                (Some(original), ReblogMode::Original | ReblogMode::Separate) => original.as_ref(),
+                _ => &mastodon_data,
+            };
+            let language = counted.language.as_deref().unwrap_or(UNKNOWN_LANGUAGE);
+            if options.languages.as_ref().is_some_and(|languages| !languages.contains(language)) {
+                aggregates.language_filtered += 1;
+                return;
+            }
+            let visibility = counted.visibility.as_deref().unwrap_or(UNKNOWN_VISIBILITY);
+            let category_excluded = (options.exclude_bots && counted.bot)
+                || (options.exclude_sensitive && (counted.sensitive || counted.has_spoiler))
+                || options.visibilities.as_ref().is_some_and(|visibilities| !visibilities.contains(visibility));
+            
+            let scores_text = options.scorer.is_some() && options.sentiment_source != SentimentSource::Field;
+            if let Some(mut original) = mastodon_data.reblog.take() {
+                match options.reblog_mode {
+                    ReblogMode::Include => {}
+                    ReblogMode::Skip => {
+                        aggregates.reblogs_skipped += 1;
+                        return;
+                    }
+                    ReblogMode::Original => {
+                        original.sentiment = original.sentiment.or(mastodon_data.sentiment);
+                        mastodon_data = *original;
+                    }
+                    ReblogMode::Separate => {
+                        if category_excluded {
+                            aggregates.category_filtered += 1;
+                            return;
+                        }
+                        original.sentiment = original.sentiment.or(mastodon_data.sentiment);
+                        let text = status_text(&original, scores_text);
+                        if let Some(sentiment) = resolve_sentiment(&original, text.as_deref(), aggregates, options) {
+                            aggregates.reblog_stats.add(sentiment);
+                            if let Some(author) = original.acct.as_ref().or(original.user_id.as_ref()) {
+                                aggregates.reblogged_authors
+                                    .entry(author.clone())
+                                    .or_default()
+                                    .add(group_user(&mastodon_data, options), sentiment);
+                            }
+                        }
+                        return;
+                    }
+                }
+            }
+            
+            let text = status_text(&mastodon_data, scores_text || options.count_words);
+            mastodon_data.sentiment = resolve_sentiment(&mastodon_data, text.as_deref(), aggregates, options);
+            
             // Skip entries without required fields
             if mastodon_data.created_at.is_none() || mastodon_data.sentiment.is_none() {
                 return;
             }
             
+            // Category breakdown is taken before the category filters so excluded groups stay comparable
+            if let Some(sentiment) = mastodon_data.sentiment {
+                for (dimension, value) in status_categories(&mastodon_data) {
+                    aggregates.category_stats
+                        .entry(dimension.to_string())
+                        .or_default()
+                        .entry(value.to_string())
+                        .or_default()
+                        .add(sentiment);
+                }
+            }
+            if category_excluded {
+                aggregates.category_filtered += 1;
+                return;
+            }
+            let language = mastodon_data.language.as_deref().unwrap_or(UNKNOWN_LANGUAGE);
+            
+            if let (true, Some(user_id), Some(sentiment)) =
+                (options.build_graph, &mastodon_data.user_id, mastodon_data.sentiment) {
+                let graph = &mut partitioned.graph;
+                let label = mastodon_data.acct.as_deref().or(mastodon_data.username.as_deref()).unwrap_or(user_id);
+                graph.add_label(user_id, label);
+                
+                // Replies mention the account they answer; that interaction counts once, as the reply
+                for (target_id, target_acct) in &mastodon_data.mentions {
+                    if target_id == user_id {
+                        continue;
+                    }
+                    graph.add_label(target_id, target_acct);
+                    if mastodon_data.in_reply_to_account_id.as_ref() != Some(target_id) {
+                        graph.add_interaction(user_id, target_id, false, sentiment);
+                    }
+                }
+                if let Some(target_id) = &mastodon_data.in_reply_to_account_id {
+                    if target_id != user_id {
+                        graph.add_interaction(user_id, target_id, true, sentiment);
+                    }
+                }
+            }
+            
+            if let (true, Some(sentiment)) = (options.build_threads, mastodon_data.sentiment) {
+                match mastodon_data.reply_link() {
+                    Some((id, parent)) => partitioned.thread_nodes.push(ThreadNode {
+                        id,
+                        parent,
+                        user_id: mastodon_data.user_id.clone(),
+                        sentiment,
+                    }),
+                    None if mastodon_data.is_reply() => aggregates.unlinked_replies += 1,
+                    None => {}
+                }
+            }
+            
+            if let Some(sentiment) = mastodon_data.sentiment {
+                aggregates.language_stats.entry(language.to_string()).or_default().add(sentiment);
+                
+                if let Some(instance) = &mastodon_data.instance {
+                    aggregates.instance_stats
+                        .entry(instance.clone())
+                        .or_default()
+                        .add(group_user(&mastodon_data, options), sentiment);
+                }
+                
+                for tag in &mastodon_data.tags {
+                    aggregates.hashtag_stats
+                        .entry(tag.clone())
+                        .or_default()
+                        .add(group_user(&mastodon_data, options), sentiment);
+                }
+                
+                add_to_groups(&mastodon_data, sentiment, aggregates, options);
+                
+                let engagement = mastodon_data.favourites_count + mastodon_data.reblogs_count + mastodon_data.replies_count;
+                if aggregates.engagement_histogram.is_empty() {
+                    aggregates.engagement_histogram = vec![EngagementStats::default(); ENGAGEMENT_HISTOGRAM_BINS];
+                }
+                aggregates.engagement_histogram[sentiment_bin(sentiment)].add(&mastodon_data, sentiment);
+                let top_posts = aggregates.top_engaged.entry(sentiment_class(sentiment).to_string()).or_default();
+                if top_posts.len() < options.engagement_top || top_posts.last().is_some_and(|last| last.engagement < engagement) {
+                    let post = EngagedPost {
+                        status_id: mastodon_data.status_id.clone(),
+                        url: mastodon_data.url.clone(),
+                        acct: mastodon_data.acct.clone(),
+                        created_at: mastodon_data.created_at.clone(),
+                        sentiment,
+                        engagement,
+                        favourites: mastodon_data.favourites_count,
+                        reblogs: mastodon_data.reblogs_count,
+                        replies: mastodon_data.replies_count,
+                    };
+                    insert_engaged_post(top_posts, post, options.engagement_top);
+                }
+                
+                if let (true, Some(text)) = (options.count_words, &text) {
+                    count_terms(text, options.stopwords.get(language), sentiment, aggregates);
+                }
+            }
+            
             // Process date
             if let Some(created_at) = &mastodon_data.created_at {
                 let created_at = created_at.replace('Z', "+00:00");
@@ -166,7 +977,57 @@ fn processing_data(
                     let hour_key = format!("{}", created_datetime.format("%Y-%m-%d %H"));
                     
                     if let Some(sentiment) = mastodon_data.sentiment {
-                        *hour_sentiment_dict.entry(hour_key).or_insert(0.0) += sentiment;
+                        *aggregates.language_hour_sentiment
+                            .entry(language.to_string())
+                            .or_default()
+                            .entry(hour_key.clone())
+                            .or_insert(0.0) += sentiment;
+                        *aggregates.hour_sentiment.entry(hour_key.clone()).or_insert(0.0) += sentiment;
+                        *aggregates.hour_count.entry(hour_key.clone()).or_insert(0) += 1;
+                        if options.quantiles {
+                            aggregates.sentiment_digest.add(sentiment);
+                            aggregates.hour_digests.entry(hour_key.clone()).or_default().add(sentiment);
+                        }
+                        if let Some(user_id) = &mastodon_data.user_id {
+                            aggregates.all_users.insert(user_id);
+                            aggregates.hour_users.entry(hour_key.clone()).or_default().insert(user_id);
+                            aggregates.day_users
+                                .entry(created_datetime.format("%Y-%m-%d").to_string())
+                                .or_default()
+                                .insert(user_id);
+                        }
+                        aggregates.hour_engagement
+                            .entry(hour_key.clone())
+                            .or_default()
+                            .add(sentiment, 1.0 + mastodon_data.favourites_count as f64
+                                + mastodon_data.reblogs_count as f64
+                                + mastodon_data.replies_count as f64);
+                        
+                        if let Some(instance) = &mastodon_data.instance {
+                            aggregates.instance_hour_stats
+                                .entry(instance.clone())
+                                .or_default()
+                                .entry(hour_key.clone())
+                                .or_default()
+                                .add(sentiment);
+                        }
+                        
+                        for tag in &mastodon_data.tags {
+                            *aggregates.hashtag_hour_count
+                                .entry(tag.clone())
+                                .or_default()
+                                .entry(hour_key.clone())
+                                .or_insert(0) += 1;
+                            
+                            if options.hashtag_watchlist.contains(tag) {
+                                aggregates.hashtag_hour_stats
+                                    .entry(tag.clone())
+                                    .or_default()
+                                    .entry(hour_key.clone())
+                                    .or_default()
+                                    .add(sentiment);
+                            }
+                        }
                     }
                 }
             }
@@ -174,8 +1035,18 @@ fn processing_data(
             // Process user sentiment
             if let (Some(user_id), Some(username), Some(sentiment)) = 
                (mastodon_data.user_id, mastodon_data.username, mastodon_data.sentiment) {
-                let entry = user_sentiment_dict.entry(user_id).or_insert((username.clone(), 0.0));
-                entry.1 += sentiment;
+                // Per-user state grows with every account, so the approximate mode keeps only fixed-size sketches
+                if let Some(params) = options.approx_users {
+                    aggregates.approx_users
+                        .get_or_insert_with(|| ApproxUsers::new(params))
+                        .add(&user_id, &username, sentiment);
+                } else {
+                    if options.quantiles {
+                        aggregates.user_digests.entry(user_id.clone()).or_default().add(sentiment);
+                    }
+                    let entry = aggregates.user_sentiment.entry(user_id).or_insert((username.clone(), 0.0));
+                    entry.1 += sentiment;
+                }
             }
         },
         Err(_) => {
@@ -184,6 +1055,39 @@ fn processing_data(
     }
 }
 
+fn status_time(created_at: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(created_at).ok().map(|time| time.with_timezone(&Utc))
+}
+
+fn parse_hour_key(hour_str: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(&format!("{hour_str}:00:00"), "%Y-%m-%d %H:%M:%S").ok()
+}
+
+// Hours before Mastodon existed or more than a day ahead of the clock come from broken
+// `createdAt` values; they are left out of the hourly series instead of stretching it
+const EARLIEST_PLAUSIBLE_HOUR: &str = "2016-01-01 00";
+
+fn hour_key(hour: NaiveDateTime) -> String {
+    format!("{}", hour.format("%Y-%m-%d %H"))
+}
+
+// Sorted hours of `hour_count` in the plausible range, with the number of posts left out
+fn plausible_hours(hour_count: &HashMap<String, usize>) -> (Vec<NaiveDateTime>, usize) {
+    let earliest = parse_hour_key(EARLIEST_PLAUSIBLE_HOUR).expect("Invalid earliest plausible hour");
+    let latest = Utc::now().naive_utc() + Duration::days(1);
+    
+    let mut hours = Vec::with_capacity(hour_count.len());
+    let mut dropped_posts = 0;
+    for (key, count) in hour_count {
+        match parse_hour_key(key) {
+            Some(hour) if hour >= earliest && hour <= latest => hours.push(hour),
+            _ => dropped_posts += count,
+        }
+    }
+    hours.sort();
+    (hours, dropped_posts)
+}
+
 fn format_hour_range(hour_str: &str) -> String {
     if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(&format!("{hour_str}:00:00"), "%Y-%m-%d %H:%M:%S") {
         let end_hour = dt.hour() + 1;
@@ -196,142 +1100,105 @@ fn format_hour_range(hour_str: &str) -> String {
     }
 }
 
-fn dump_happiest_hours(happy_hours: &[(String, f64)], output_dir: &Path) {
+// The happiest rankings have always put a plus before the score, the saddest nothing
+fn happy_score(score: f64) -> String {
+    format!("+{}", score)
+}
+
+fn sad_score(score: f64) -> String {
+    score.to_string()
+}
+
+fn signed_score(score: f64) -> String {
+    format!("{:+}", score)
+}
+
+// Numbered lines of an hour ranking, with scores written by `format_score`
+fn hour_ranking_lines(hours: &[(String, f64)], format_score: fn(f64) -> String) -> Vec<String> {
+    hours.iter()
+        .enumerate()
+        .map(|(i, (hour, score))| {
+            format!("{}. {} with sentiment {}", i + 1, format_hour_range(hour), format_score(*score))
+        })
+        .collect()
+}
+
+// Numbered lines of a user ranking, with scores written by `format_score`
+fn user_ranking_lines(users: &[(String, (String, f64))], format_score: fn(f64) -> String) -> Vec<String> {
+    users.iter()
+        .enumerate()
+        .map(|(i, (user_id, (username, score)))| {
+            format!("{}. {} (ID: {}) with total sentiment {}", i + 1, username, user_id, format_score(*score))
+        })
+        .collect()
+}
+
+fn print_ranking(title: &str, lines: &[String]) {
     println!("{}", SEPARATOR);
-    println!("Top Happiest Hours");
+    println!("{}", title);
     println!("{}", SEPARATOR);
-    
-    let mut output = Vec::new();
-    for (i, (hour, score)) in happy_hours.iter().enumerate() {
-        let formatted_hour = format_hour_range(hour);
-        let line = format!("{}. {} with sentiment +{}", i + 1, formatted_hour, score);
+    for line in lines {
         println!("{}", line);
-        output.push(line);
     }
     println!();
-    
-    // Create output directory if it doesn't exist
+}
+
+// Create (or truncate) `file_name` in `output_dir`, creating the directory if it doesn't exist
+fn create_output_file(output_dir: &Path, file_name: &str) -> BufWriter<File> {
     fs::create_dir_all(output_dir).expect("Failed to create output directory");
-    
-    // Write to file
-    let output_file = output_dir.join("happiest_hours.txt");
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(output_file)
-        .expect("Failed to open happiest_hours.txt for writing");
-    
-    let mut writer = BufWriter::new(file);
-    writeln!(writer, "Top Happiest Hours").expect("Failed to write to file");
-    writeln!(writer, "{}", SEPARATOR).expect("Failed to write to file");
-    
-    for line in output {
-        writeln!(writer, "{}", line).expect("Failed to write to file");
-    }
+    let file = File::create(output_dir.join(file_name))
+        .unwrap_or_else(|_| panic!("Failed to open {} for writing", file_name));
+    BufWriter::new(file)
 }
 
-fn dump_saddest_hours(sad_hours: &[(String, f64)], output_dir: &Path) {
-    println!("{}", SEPARATOR);
-    println!("Top Saddest Hours");
-    println!("{}", SEPARATOR);
-    
-    let mut output = Vec::new();
-    for (i, (hour, score)) in sad_hours.iter().enumerate() {
-        let formatted_hour = format_hour_range(hour);
-        let line = format!("{}. {} with sentiment {}", i + 1, formatted_hour, score);
-        println!("{}", line);
-        output.push(line);
+fn write_lines<S: AsRef<str>>(file_name: &str, lines: impl IntoIterator<Item = S>, output_dir: &Path) {
+    let mut writer = create_output_file(output_dir, file_name);
+    for line in lines {
+        writeln!(writer, "{}", line.as_ref()).expect("Failed to write to file");
     }
-    println!();
-    
-    // Create output directory if it doesn't exist
-    fs::create_dir_all(output_dir).expect("Failed to create output directory");
-    
-    // Write to file
-    let output_file = output_dir.join("saddest_hours.txt");
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(output_file)
-        .expect("Failed to open saddest_hours.txt for writing");
-    
-    let mut writer = BufWriter::new(file);
-    writeln!(writer, "Top Saddest Hours").expect("Failed to write to file");
-    writeln!(writer, "{}", SEPARATOR).expect("Failed to write to file");
-    
-    for line in output {
-        writeln!(writer, "{}", line).expect("Failed to write to file");
+}
+
+fn write_ranking(title: &str, file_name: &str, lines: &[String], output_dir: &Path) {
+    let heading = [title, SEPARATOR];
+    write_lines(file_name, heading.into_iter().chain(lines.iter().map(String::as_str)), output_dir);
+}
+
+// A CSV field, quoted when it holds a separator, quote or line break
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
     }
 }
 
+// Write a CSV file with `header` and one line per row; text fields in the rows go through `csv_field`
+fn write_csv(file_name: &str, header: &str, rows: impl IntoIterator<Item = String>, output_dir: &Path) {
+    write_lines(file_name, std::iter::once(header.to_string()).chain(rows), output_dir);
+}
+
+fn dump_happiest_hours(happy_hours: &[(String, f64)], output_dir: &Path) {
+    let lines = hour_ranking_lines(happy_hours, happy_score);
+    print_ranking("Top Happiest Hours", &lines);
+    write_ranking("Top Happiest Hours", "happiest_hours.txt", &lines, output_dir);
+}
+
+fn dump_saddest_hours(sad_hours: &[(String, f64)], output_dir: &Path) {
+    let lines = hour_ranking_lines(sad_hours, sad_score);
+    print_ranking("Top Saddest Hours", &lines);
+    write_ranking("Top Saddest Hours", "saddest_hours.txt", &lines, output_dir);
+}
+
 fn dump_happiest_users(happy_users: &[(String, (String, f64))], output_dir: &Path) {
-    println!("{}", SEPARATOR);
-    println!("Top Happiest Users");
-    println!("{}", SEPARATOR);
-    
-    let mut output = Vec::new();
-    for (i, (user_id, (username, score))) in happy_users.iter().enumerate() {
-        let line = format!("{}. {} (ID: {}) with total sentiment +{}", i + 1, username, user_id, score);
-        println!("{}", line);
-        output.push(line);
-    }
-    println!();
-    
-    // Create output directory if it doesn't exist
-    fs::create_dir_all(output_dir).expect("Failed to create output directory");
-    
-    // Write to file
-    let output_file = output_dir.join("happiest_users.txt");
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(output_file)
-        .expect("Failed to open happiest_users.txt for writing");
-    
-    let mut writer = BufWriter::new(file);
-    writeln!(writer, "Top Happiest Users").expect("Failed to write to file");
-    writeln!(writer, "{}", SEPARATOR).expect("Failed to write to file");
-    
-    for line in output {
-        writeln!(writer, "{}", line).expect("Failed to write to file");
-    }
+    let lines = user_ranking_lines(happy_users, happy_score);
+    print_ranking("Top Happiest Users", &lines);
+    write_ranking("Top Happiest Users", "happiest_users.txt", &lines, output_dir);
 }
 
 fn dump_saddest_users(sad_users: &[(String, (String, f64))], output_dir: &Path) {
-    println!("{}", SEPARATOR);
-    println!("Top Saddest Users");
-    println!("{}", SEPARATOR);
-    
-    let mut output = Vec::new();
-    for (i, (user_id, (username, score))) in sad_users.iter().enumerate() {
-        let line = format!("{}. {} (ID: {}) with total sentiment {}", i + 1, username, user_id, score);
-        println!("{}", line);
-        output.push(line);
-    }
-    println!();
-    
-    // Create output directory if it doesn't exist
-    fs::create_dir_all(output_dir).expect("Failed to create output directory");
-    
-    // Write to file
-    let output_file = output_dir.join("saddest_users.txt");
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(output_file)
-        .expect("Failed to open saddest_users.txt for writing");
-    
-    let mut writer = BufWriter::new(file);
-    writeln!(writer, "Top Saddest Users").expect("Failed to write to file");
-    writeln!(writer, "{}", SEPARATOR).expect("Failed to write to file");
-    
-    for line in output {
-        writeln!(writer, "{}", line).expect("Failed to write to file");
-    }
+    let lines = user_ranking_lines(sad_users, sad_score);
+    print_ranking("Top Saddest Users", &lines);
+    write_ranking("Top Saddest Users", "saddest_users.txt", &lines, output_dir);
 }
 
 fn dump_time(comm_rank: i32, title: &str, time_period: f64) {
@@ -380,7 +1247,7 @@ impl Eq for UserSentimentItem {}
 
 impl PartialOrd for UserSentimentItem {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.sentiment.partial_cmp(&other.sentiment)
+        Some(self.cmp(other))
     }
 }
 
@@ -393,16 +1260,64 @@ impl Ord for UserSentimentItem {
 // -----------------------------------
 // Main processing functions - from main.py
 // -----------------------------------
+// Scan the remaining segments of `scan`, recording the last line handled in each segment
 fn process_chunk_memory_mapped(
+    input_file: &str,
+    max_buffer_size: usize,
+    options: &ProcessingOptions,
+    duplicate_offsets: &HashSet<u64>,
+    scan: &mut ScanProgress,
+) {
+    let ScanProgress { segments, state, spill, checkpointer } = scan;
+    for i in 0..segments.len() {
+        let ScanSegment { end, .. } = segments[i];
+        let start = segments[i].resume_start();
+        for_each_chunk_line(input_file, start, end, max_buffer_size, |offset, line| {
+            if duplicate_offsets.contains(&offset) {
+                state.aggregates.duplicates_skipped += 1;
+            } else {
+                processing_data(line, &mut state.aggregates, &mut state.partitioned, options);
+                if let Some(spill) = spill.as_mut() {
+                    spill.spill_if_needed(&mut state.aggregates);
+                }
+            }
+            state.lines_processed += 1;
+            segments[i].last_line = Some(offset);
+            if let Some(checkpointer) = checkpointer.as_mut() {
+                checkpointer.write_if_due(segments, state);
+            }
+        });
+    }
+}
+
+// Offset of the first line that starts at or after `offset`
+fn next_line_start(mmap: &[u8], offset: usize) -> usize {
+    if offset == 0 {
+        return 0;
+    }
+    mmap[offset - 1..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(mmap.len(), |pos| offset + pos)
+}
+
+// Call `handle` with the file offset and preprocessed text of every line that starts after
+// local_start (or at it, when it is 0) and no later than local_end, returning how many lines
+// were handled. Adjacent ranges therefore see every line exactly once.
+fn for_each_chunk_line<F: FnMut(u64, &str)>(
     input_file: &str,
     local_start: u64,
     local_end: u64,
     max_buffer_size: usize,
-) -> (HashMap<String, f64>, HashMap<String, (String, f64)>, usize) {
-    let mut local_hour_sentiment = HashMap::new();
-    let mut local_user_sentiment = HashMap::new();
+    mut handle: F,
+) -> usize {
     let mut lines_processed = 0;
     
+    // An empty range holds no lines, not even the one at offset 0
+    if local_start >= local_end {
+        return lines_processed;
+    }
+    
     // Open file with memory mapping
     let file = File::open(input_file).expect("Failed to open input file");
     let mmap = unsafe { MmapOptions::new().map(&file).expect("Failed to map file") };
@@ -416,7 +1331,7 @@ fn process_chunk_memory_mapped(
             position += pos + 1; // Move past the newline
         } else {
             // No more newlines
-            return (local_hour_sentiment, local_user_sentiment, lines_processed);
+            return lines_processed;
         }
     }
     
@@ -424,17 +1339,18 @@ fn process_chunk_memory_mapped(
     let mut current_pos = position;
     let mut segment_start = current_pos;
     
-    while current_pos < local_end as usize && current_pos < mmap.len() {
+    while current_pos <= local_end as usize && current_pos < mmap.len() {
         // Calculate the end of the current segment
         let segment_end = min(segment_start + max_buffer_size, local_end as usize);
         let segment_end = min(segment_end, mmap.len());
         
-        // Adjust segment_end to the next newline if not at the end of our chunk
+        // Adjust segment_end to the next newline, so the last line of our chunk is read in full
         let mut adjusted_segment_end = segment_end;
-        if segment_end < local_end as usize && segment_end < mmap.len() {
-            if let Some(pos) = mmap[segment_end..].iter().position(|&b| b == b'\n') {
-                adjusted_segment_end = segment_end + pos + 1;
-            }
+        if segment_end < mmap.len() {
+            adjusted_segment_end = match mmap[segment_end..].iter().position(|&b| b == b'\n') {
+                Some(pos) => segment_end + pos + 1,
+                None => mmap.len(),
+            };
         }
         
         // Process lines in the current segment
@@ -454,8 +1370,7 @@ fn process_chunk_memory_mapped(
                 if let Ok(line) = std::str::from_utf8(&mmap[current_pos..next_newline]) {
                     // Use the preprocess_data function
                     if let Some(pre_line) = preprocess_data(line) {
-                        // Use the processing_data function
-                        processing_data(&pre_line, &mut local_hour_sentiment, &mut local_user_sentiment);
+                        handle(current_pos as u64, &pre_line);
                         lines_processed += 1;
                     }
                 }
@@ -467,51 +1382,382 @@ fn process_chunk_memory_mapped(
                 break;
             }
         }
-        
-        segment_start = current_pos;
+        
+        segment_start = current_pos;
+    }
+    
+    lines_processed
+}
+
+fn merge_hour_dicts(dicts_list: Vec<HashMap<String, f64>>) -> HashMap<String, f64> {
+    let mut merged = HashMap::new();
+    for dict in dicts_list {
+        for (hour, sentiment) in dict {
+            *merged.entry(hour).or_insert(0.0) += sentiment;
+        }
+    }
+    merged
+}
+
+fn merge_user_dicts(dicts_list: Vec<HashMap<String, (String, f64)>>) -> HashMap<String, (String, f64)> {
+    let mut merged = HashMap::new();
+    for dict in dicts_list {
+        for (uid, (username, sentiment)) in dict {
+            let entry = merged.entry(uid).or_insert((username.clone(), 0.0));
+            entry.1 += sentiment;
+        }
+    }
+    merged
+}
+
+fn merge_count_dicts(dicts_list: Vec<HashMap<String, usize>>) -> HashMap<String, usize> {
+    let mut merged = HashMap::new();
+    for dict in dicts_list {
+        for (key, count) in dict {
+            *merged.entry(key).or_insert(0) += count;
+        }
+    }
+    merged
+}
+
+fn merge_stats_dicts(dicts_list: Vec<HashMap<String, SentimentStats>>) -> HashMap<String, SentimentStats> {
+    let mut merged: HashMap<String, SentimentStats> = HashMap::new();
+    for dict in dicts_list {
+        for (key, stats) in dict {
+            merged.entry(key).or_default().merge(&stats);
+        }
+    }
+    merged
+}
+
+fn merge_summary_dicts(dicts_list: Vec<HashMap<String, SentimentSummary>>) -> HashMap<String, SentimentSummary> {
+    let mut merged: HashMap<String, SentimentSummary> = HashMap::new();
+    for dict in dicts_list {
+        for (key, summary) in dict {
+            merged
+                .entry(key)
+                .and_modify(|existing| existing.merge(&summary))
+                .or_insert(summary);
+        }
+    }
+    merged
+}
+
+fn merge_sketch_dicts(dicts_list: Vec<HashMap<String, HyperLogLog>>) -> HashMap<String, HyperLogLog> {
+    let mut merged: HashMap<String, HyperLogLog> = HashMap::new();
+    for dict in dicts_list {
+        for (key, sketch) in dict {
+            merged.entry(key).or_default().merge(&sketch);
+        }
+    }
+    merged
+}
+
+fn merge_digest_dicts(dicts_list: Vec<HashMap<String, TDigest>>) -> HashMap<String, TDigest> {
+    let mut merged: HashMap<String, TDigest> = HashMap::new();
+    for dict in dicts_list {
+        for (key, digest) in dict {
+            merged.entry(key).or_default().merge(&digest);
+        }
     }
-    
-    (local_hour_sentiment, local_user_sentiment, lines_processed)
+    merged
 }
 
-fn merge_hour_dicts(dicts_list: Vec<HashMap<String, f64>>) -> HashMap<String, f64> {
-    let mut merged = HashMap::new();
+fn merge_group_dicts(dicts_list: Vec<HashMap<String, GroupStats>>) -> HashMap<String, GroupStats> {
+    let mut merged: HashMap<String, GroupStats> = HashMap::new();
     for dict in dicts_list {
-        for (hour, sentiment) in dict {
-            *merged.entry(hour).or_insert(0.0) += sentiment;
+        for (key, stats) in dict {
+            merged.entry(key).or_default().merge(stats);
         }
     }
     merged
 }
 
-fn merge_user_dicts(dicts_list: Vec<HashMap<String, (String, f64)>>) -> HashMap<String, (String, f64)> {
-    let mut merged = HashMap::new();
+fn merge_weighted_dicts(dicts_list: Vec<HashMap<String, WeightedSentiment>>) -> HashMap<String, WeightedSentiment> {
+    let mut merged: HashMap<String, WeightedSentiment> = HashMap::new();
     for dict in dicts_list {
-        for (uid, (username, sentiment)) in dict {
-            let entry = merged.entry(uid).or_insert((username.clone(), 0.0));
-            entry.1 += sentiment;
+        for (hour, weighted) in dict {
+            merged.entry(hour).or_default().merge(&weighted);
+        }
+    }
+    merged
+}
+
+fn merge_term_dicts(dicts_list: Vec<HashMap<String, TermCounts>>) -> HashMap<String, TermCounts> {
+    let mut merged: HashMap<String, TermCounts> = HashMap::new();
+    for dict in dicts_list {
+        for (term, counts) in dict {
+            merged.entry(term).or_default().merge(&counts);
         }
     }
     merged
 }
 
+// Merge two-level dictionaries (e.g. language -> hour -> value) with `merge_inner` for the inner level
+fn merge_nested_dicts<V, F>(
+    dicts_list: Vec<HashMap<String, HashMap<String, V>>>,
+    merge_inner: F,
+) -> HashMap<String, HashMap<String, V>>
+where
+    F: Fn(Vec<HashMap<String, V>>) -> HashMap<String, V>,
+{
+    let mut grouped: HashMap<String, Vec<HashMap<String, V>>> = HashMap::new();
+    for dict in dicts_list {
+        for (key, inner_dict) in dict {
+            grouped.entry(key).or_default().push(inner_dict);
+        }
+    }
+    grouped
+        .into_iter()
+        .map(|(key, inner_dicts)| (key, merge_inner(inner_dicts)))
+        .collect()
+}
+
+fn merge_aggregates(aggregates_list: Vec<Aggregates>) -> Aggregates {
+    let mut hour_sentiment_list = Vec::with_capacity(aggregates_list.len());
+    let mut hour_count_list = Vec::with_capacity(aggregates_list.len());
+    let mut user_sentiment_list = Vec::with_capacity(aggregates_list.len());
+    let mut language_stats_list = Vec::with_capacity(aggregates_list.len());
+    let mut language_hour_list = Vec::with_capacity(aggregates_list.len());
+    let mut hashtag_stats_list = Vec::with_capacity(aggregates_list.len());
+    let mut hashtag_hour_list = Vec::with_capacity(aggregates_list.len());
+    let mut hashtag_hour_count_list = Vec::with_capacity(aggregates_list.len());
+    let mut instance_stats_list = Vec::with_capacity(aggregates_list.len());
+    let mut instance_hour_list = Vec::with_capacity(aggregates_list.len());
+    let mut word_counts_list = Vec::with_capacity(aggregates_list.len());
+    let mut bigram_counts_list = Vec::with_capacity(aggregates_list.len());
+    let mut hour_engagement_list = Vec::with_capacity(aggregates_list.len());
+    let mut sentiment_comparison = SentimentComparison::default();
+    let mut engagement_histogram = Vec::new();
+    let mut top_engaged: HashMap<String, Vec<EngagedPost>> = HashMap::new();
+    let mut reblogged_authors_list = Vec::with_capacity(aggregates_list.len());
+    let mut category_stats_list = Vec::with_capacity(aggregates_list.len());
+    let mut language_filtered = 0;
+    let mut category_filtered = 0;
+    let mut expression_filtered = 0;
+    let mut time_filtered = 0;
+    let mut unlinked_replies = 0;
+    let mut grouped_list = Vec::with_capacity(aggregates_list.len());
+    let mut hour_users_list = Vec::with_capacity(aggregates_list.len());
+    let mut day_users_list = Vec::with_capacity(aggregates_list.len());
+    let mut all_users = HyperLogLog::default();
+    let mut hour_digests_list = Vec::with_capacity(aggregates_list.len());
+    let mut user_digests_list = Vec::with_capacity(aggregates_list.len());
+    let mut sentiment_digest = TDigest::default();
+    let mut approx_users: Option<ApproxUsers> = None;
+    let mut spill_files = 0;
+    let mut duplicates_skipped = 0;
+    let mut reblogs_skipped = 0;
+    let mut reblog_stats = SentimentStats::default();
+    for aggregates in aggregates_list {
+        duplicates_skipped += aggregates.duplicates_skipped;
+        reblogs_skipped += aggregates.reblogs_skipped;
+        reblog_stats.merge(&aggregates.reblog_stats);
+        reblogged_authors_list.push(aggregates.reblogged_authors);
+        category_stats_list.push(aggregates.category_stats);
+        language_filtered += aggregates.language_filtered;
+        category_filtered += aggregates.category_filtered;
+        expression_filtered += aggregates.expression_filtered;
+        time_filtered += aggregates.time_filtered;
+        unlinked_replies += aggregates.unlinked_replies;
+        grouped_list.push(aggregates.grouped);
+        hour_users_list.push(aggregates.hour_users);
+        day_users_list.push(aggregates.day_users);
+        all_users.merge(&aggregates.all_users);
+        hour_digests_list.push(aggregates.hour_digests);
+        user_digests_list.push(aggregates.user_digests);
+        sentiment_digest.merge(&aggregates.sentiment_digest);
+        spill_files += aggregates.spill_files;
+        match (&mut approx_users, aggregates.approx_users) {
+            (Some(merged), Some(approx)) => merged.merge(&approx),
+            (merged, approx) => *merged = merged.take().or(approx),
+        }
+        merge_engagement_histograms(&mut engagement_histogram, &aggregates.engagement_histogram);
+        for (class, posts) in aggregates.top_engaged {
+            top_engaged.entry(class).or_default().extend(posts);
+        }
+        sentiment_comparison.merge(&aggregates.sentiment_comparison);
+        hour_sentiment_list.push(aggregates.hour_sentiment);
+        hour_count_list.push(aggregates.hour_count);
+        user_sentiment_list.push(aggregates.user_sentiment);
+        language_stats_list.push(aggregates.language_stats);
+        language_hour_list.push(aggregates.language_hour_sentiment);
+        hashtag_stats_list.push(aggregates.hashtag_stats);
+        hashtag_hour_list.push(aggregates.hashtag_hour_stats);
+        hashtag_hour_count_list.push(aggregates.hashtag_hour_count);
+        instance_stats_list.push(aggregates.instance_stats);
+        instance_hour_list.push(aggregates.instance_hour_stats);
+        word_counts_list.push(aggregates.word_counts);
+        bigram_counts_list.push(aggregates.bigram_counts);
+        hour_engagement_list.push(aggregates.hour_engagement);
+    }
+    for posts in top_engaged.values_mut() {
+        posts.sort_by_key(|post| Reverse(post.engagement));
+    }
+    
+    Aggregates {
+        hour_sentiment: merge_hour_dicts(hour_sentiment_list),
+        hour_count: merge_count_dicts(hour_count_list),
+        user_sentiment: merge_user_dicts(user_sentiment_list),
+        language_stats: merge_stats_dicts(language_stats_list),
+        language_hour_sentiment: merge_nested_dicts(language_hour_list, merge_hour_dicts),
+        hashtag_stats: merge_group_dicts(hashtag_stats_list),
+        hashtag_hour_stats: merge_nested_dicts(hashtag_hour_list, merge_stats_dicts),
+        hashtag_hour_count: merge_nested_dicts(hashtag_hour_count_list, merge_count_dicts),
+        instance_stats: merge_group_dicts(instance_stats_list),
+        instance_hour_stats: merge_nested_dicts(instance_hour_list, merge_stats_dicts),
+        sentiment_comparison,
+        word_counts: merge_term_dicts(word_counts_list),
+        bigram_counts: merge_term_dicts(bigram_counts_list),
+        engagement_histogram,
+        hour_engagement: merge_weighted_dicts(hour_engagement_list),
+        top_engaged,
+        duplicates_skipped,
+        reblogs_skipped,
+        reblog_stats,
+        reblogged_authors: merge_group_dicts(reblogged_authors_list),
+        category_stats: merge_nested_dicts(category_stats_list, merge_stats_dicts),
+        language_filtered,
+        category_filtered,
+        expression_filtered,
+        time_filtered,
+        unlinked_replies,
+        grouped: merge_nested_dicts(grouped_list, merge_summary_dicts),
+        all_users,
+        hour_users: merge_sketch_dicts(hour_users_list),
+        day_users: merge_sketch_dicts(day_users_list),
+        sentiment_digest,
+        hour_digests: merge_digest_dicts(hour_digests_list),
+        user_digests: merge_digest_dicts(user_digests_list),
+        approx_users,
+        spill_files,
+    }
+}
+
+// Serialize a value on every rank and collect them on rank 0 (rank 0's own value first)
+fn gather_at_root<C: Communicator, T: Serialize + DeserializeOwned>(
+    world: &C,
+    rank: usize,
+    size: usize,
+    local: T,
+) -> Vec<T> {
+    if rank == 0 {
+        let mut gathered = Vec::with_capacity(size);
+        gathered.push(local);
+        for i in 1..size {
+            let from_rank = i as i32;
+            let (bytes, _) = world.process_at_rank(from_rank).receive_vec::<u8>();
+            gathered.push(serde_json::from_slice(&bytes).expect("Failed to deserialize gathered data"));
+        }
+        gathered
+    } else {
+        let bytes = serde_json::to_vec(&local).expect("Failed to serialize data for gathering");
+        world.process_at_rank(0).send(&bytes[..]);
+        Vec::new()
+    }
+}
+
+// Compute a value on rank 0 only and hand every rank a copy of it
+fn broadcast_from_root<C: Communicator, T: Serialize + DeserializeOwned, F: FnOnce() -> T>(
+    world: &C,
+    rank: usize,
+    size: usize,
+    compute: F,
+) -> T {
+    if rank == 0 {
+        let value = compute();
+        let bytes = serde_json::to_vec(&value).expect("Failed to serialize data for broadcasting");
+        for i in 1..size {
+            world.process_at_rank(i as i32).send(&bytes[..]);
+        }
+        value
+    } else {
+        let (bytes, _) = world.process_at_rank(0).receive_vec::<u8>();
+        serde_json::from_slice(&bytes).expect("Failed to deserialize broadcast data")
+    }
+}
+
+// Send `outgoing[i]` to rank i and return what every rank sent to this one, indexed by source rank.
+// Ranks take turns as the sender, so blocking sends never wait on each other.
+fn exchange_between_ranks<C: Communicator, T: Serialize + DeserializeOwned>(
+    world: &C,
+    rank: usize,
+    size: usize,
+    outgoing: Vec<T>,
+) -> Vec<T> {
+    let mut outgoing = Some(outgoing);
+    let mut incoming: Vec<Option<T>> = (0..size).map(|_| None).collect();
+    
+    for source in 0..size {
+        if source == rank {
+            let items = outgoing.take().expect("Outgoing data already sent");
+            for (dest, item) in items.into_iter().enumerate() {
+                if dest == rank {
+                    incoming[rank] = Some(item);
+                } else {
+                    let bytes = serde_json::to_vec(&item).expect("Failed to serialize data for exchange");
+                    world.process_at_rank(dest as i32).send(&bytes[..]);
+                }
+            }
+        } else {
+            let (bytes, _) = world.process_at_rank(source as i32).receive_vec::<u8>();
+            incoming[source] = Some(serde_json::from_slice(&bytes).expect("Failed to deserialize exchanged data"));
+        }
+    }
+    
+    incoming.into_iter().map(|item| item.expect("Missing data from rank")).collect()
+}
+
+// Stable FNV-1a hash, identical on every rank
+fn stable_hash(key: &str) -> u64 {
+    stable_hash_bytes(key.as_bytes())
+}
+
+fn stable_hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// Rank responsible for a key when state is hash-partitioned across ranks
+fn owner_rank(key: &str, size: usize) -> usize {
+    (stable_hash(key) % size as u64) as usize
+}
+
+// Sum of `local` over every rank
+fn all_reduce_sum<C: Communicator>(world: &C, local: f64) -> f64 {
+    let mut global = 0.0;
+    world.all_reduce_into(&local, &mut global, SystemOperation::sum());
+    global
+}
+
 fn setup_mpi_file_boundaries(input_file: &str, rank: usize, size: usize) -> (u64, u64, u64) {
     let metadata = fs::metadata(input_file).expect("Failed to get file metadata");
     let file_size = metadata.size();
     
-    let chunk_size = file_size / size as u64;
-    let local_start = rank as u64 * chunk_size;
+    let (local_start, local_end) = setup_mpi_range_boundaries(0, file_size, rank, size);
+    
+    (local_start, local_end, file_size)
+}
+
+// Split the byte range [range_start, range_end) evenly between the ranks
+fn setup_mpi_range_boundaries(range_start: u64, range_end: u64, rank: usize, size: usize) -> (u64, u64) {
+    let chunk_size = (range_end - range_start) / size as u64;
+    let local_start = range_start + rank as u64 * chunk_size;
     let local_end = if rank == size - 1 {
-        file_size
+        range_end
     } else {
-        (rank as u64 + 1) * chunk_size
+        range_start + (rank as u64 + 1) * chunk_size
     };
     
-    (local_start, local_end, file_size)
+    (local_start, local_end)
 }
 
 // Function to find top-n items by value
-fn top_n_by_value<T: Clone>(map: &HashMap<T, f64>, n: usize, largest: bool) -> Vec<(T, f64)> 
+fn top_n_by_value<T>(map: &HashMap<T, f64>, n: usize, largest: bool) -> Vec<(T, f64)> 
 where T: Ord + Clone 
 {
     let mut heap = BinaryHeap::with_capacity(n + 1);
@@ -523,14 +1769,15 @@ where T: Ord + Clone
             SentimentItem { key: key.clone(), value: -value }
         };
         
-        heap.push(item);
+        // Min-heap of the n best items seen so far
+        heap.push(Reverse(item));
         if heap.len() > n {
             heap.pop();
         }
     }
     
     let mut result = Vec::with_capacity(n);
-    while let Some(item) = heap.pop() {
+    while let Some(Reverse(item)) = heap.pop() {
         let value = if largest { item.value } else { -item.value };
         result.push((item.key, value));
     }
@@ -550,14 +1797,15 @@ fn top_n_users(map: &HashMap<String, (String, f64)>, n: usize, largest: bool) ->
             sentiment: if largest { *sentiment } else { -sentiment },
         };
         
-        heap.push(item);
+        // Min-heap of the n best users seen so far
+        heap.push(Reverse(item));
         if heap.len() > n {
             heap.pop();
         }
     }
     
     let mut result = Vec::with_capacity(n);
-    while let Some(item) = heap.pop() {
+    while let Some(Reverse(item)) = heap.pop() {
         let sentiment = if largest { item.sentiment } else { -item.sentiment };
         result.push((item.user_id, (item.username, sentiment)));
     }
@@ -566,6 +1814,111 @@ fn top_n_users(map: &HashMap<String, (String, f64)>, n: usize, largest: bool) ->
     result
 }
 
+// -----------------------------------
+// Command-line arguments
+// -----------------------------------
+// Arguments that decide which statuses are counted and how they are scored, shared by the
+// batch analysis and the watch subcommand
+fn record_args() -> Vec<Arg> {
+    vec![
+        Arg::new("language")
+            .long("language")
+            .value_name("CODES")
+            .help("Only process statuses in these comma-separated languages, e.g. en,de (use 'und' for unknown)")
+            .required(false),
+        Arg::new("hashtag-watchlist")
+            .long("hashtag-watchlist")
+            .value_name("TAGS")
+            .help("Comma-separated hashtags to report as hourly series")
+            .required(false),
+        Arg::new("sentiment-source")
+            .long("sentiment-source")
+            .value_name("SOURCE")
+            .help("Take sentiment from the precomputed 'field', score the content with the 'lexicon', or use 'both' (field first, lexicon as fallback)")
+            .value_parser(["field", "lexicon", "both"])
+            .default_value("field"),
+        Arg::new("lexicon")
+            .long("lexicon")
+            .value_name("FILE")
+            .help("AFINN-style lexicon of tab-separated word and valence lines (default: bundled lexicon)")
+            .required(false),
+        Arg::new("reblogs")
+            .long("reblogs")
+            .value_name("MODE")
+            .help("Count reblogs for the booster ('include'), drop them ('skip'), count the reblogged status for its author ('original') or report them on their own ('separate')")
+            .value_parser(["include", "skip", "original", "separate"])
+            .default_value("include"),
+        Arg::new("exclude-bots")
+            .long("exclude-bots")
+            .help("Skip statuses from accounts flagged as bots")
+            .action(ArgAction::SetTrue),
+        Arg::new("exclude-sensitive")
+            .long("exclude-sensitive")
+            .help("Skip statuses marked sensitive or carrying a content warning")
+            .action(ArgAction::SetTrue),
+        Arg::new("visibility")
+            .long("visibility")
+            .value_name("LEVELS")
+            .help("Only process statuses with these comma-separated visibilities, e.g. public,unlisted")
+            .required(false),
+        Arg::new("where")
+            .long("where")
+            .value_name("EXPR")
+            .help("Only process statuses matching this expression over their JSON fields, e.g. 'language == \"en\" && followersCount > 100'")
+            .value_parser(|source: &str| FilterExpr::parse(source))
+            .required(false),
+        Arg::new("from")
+            .long("from")
+            .value_name("TIME")
+            .help("Only process statuses created at or after this time (RFC 3339 or YYYY-MM-DD, UTC)")
+            .value_parser(|value: &str| parse_time_bound(value))
+            .required(false),
+        Arg::new("to")
+            .long("to")
+            .value_name("TIME")
+            .help("Only process statuses created before this time (RFC 3339 or YYYY-MM-DD, UTC)")
+            .value_parser(|value: &str| parse_time_bound(value))
+            .required(false),
+    ]
+}
+
+// Record-level processing options from the arguments of `record_args`; the batch analysis adds
+// its own report options on top
+fn record_options(matches: &ArgMatches) -> io::Result<ProcessingOptions> {
+    // Sentiment scoring
+    let sentiment_source = matches.get_one::<String>("sentiment-source")
+        .and_then(|source| SentimentSource::parse(source))
+        .unwrap_or_default();
+    let scorer: Option<Arc<dyn SentimentScorer>> = if sentiment_source == SentimentSource::Field {
+        None
+    } else if let Some(path) = matches.get_one::<String>("lexicon") {
+        Some(Arc::new(LexiconScorer::from_file(path)?))
+    } else {
+        Some(Arc::new(LexiconScorer::bundled()))
+    };
+    
+    // Record filters
+    Ok(ProcessingOptions {
+        languages: matches.get_one::<String>("language").map(|codes| parse_list_option(codes)),
+        hashtag_watchlist: matches.get_one::<String>("hashtag-watchlist")
+            .map(|tags| parse_list_option(tags))
+            .unwrap_or_default(),
+        sentiment_source,
+        scorer,
+        reblog_mode: matches.get_one::<String>("reblogs")
+            .and_then(|mode| ReblogMode::parse(mode))
+            .unwrap_or_default(),
+        exclude_bots: matches.get_flag("exclude-bots"),
+        exclude_sensitive: matches.get_flag("exclude-sensitive"),
+        visibilities: matches.get_one::<String>("visibility").map(|levels| parse_list_option(levels)),
+        // Compiled once by the argument parser and evaluated for every record
+        filter: matches.get_one::<FilterExpr>("where").cloned(),
+        from: matches.get_one::<DateTime<Utc>>("from").copied(),
+        to: matches.get_one::<DateTime<Utc>>("to").copied(),
+        ..Default::default()
+    })
+}
+
 // -----------------------------------
 // Main function - entry point
 // -----------------------------------
@@ -597,13 +1950,283 @@ fn main() -> io::Result<()> {
             .value_name("SIZE")
             .help("Buffer size in MB for processing chunks (default: 100)")
             .default_value("100"))
+        .arg(Arg::new("memory-limit")
+            .long("memory-limit")
+            .value_name("SIZE")
+            .help("Memory in MB for the per-rank user and hour maps; beyond it they are spilled to sorted \
+                   files under the log directory and merged at the end (rank 0 then only holds totals for the top users). \
+                   Hashtag, instance and reblogged-author reports leave out their distinct-user estimates, and \
+                   --quantiles and --group-by, whose per-key state is not spilled, cannot be combined with it")
+            .value_parser(|value: &str| parse_positive_count(value))
+            .conflicts_with_all(["checkpoint-dir", "quantiles", "group-by"]))
+        .arg(Arg::new("checkpoint-dir")
+            .long("checkpoint-dir")
+            .value_name("DIR")
+            .help("Periodically save every rank's scan position and partial aggregates to DIR"))
+        .arg(Arg::new("checkpoint-interval")
+            .long("checkpoint-interval")
+            .value_name("SECONDS")
+            .help("Seconds between checkpoints of a rank (default: 600)")
+            .default_value("600"))
+        .arg(Arg::new("resume")
+            .long("resume")
+            .help("Continue from the last consistent checkpoint in --checkpoint-dir, with any number of ranks")
+            .requires("checkpoint-dir")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("state")
+            .long("state")
+            .value_name("FILE")
+            .help("Keep the aggregates and the processed length of the input in FILE, so that the next run \
+                   only reads lines appended since and merges them in (--dedup only sees the new lines). \
+                   Truncating the input or rewriting its first or last 64 KiB is detected and starts over; \
+                   edits elsewhere in the processed part are not")
+            .conflicts_with_all(["time-ordered", "memory-limit", "graph-export", "pagerank", "threads", "resume"]))
+        .arg(Arg::new("anomaly-window")
+            .long("anomaly-window")
+            .value_name("HOURS")
+            .help("Number of preceding hours used as the anomaly baseline (default: 24)")
+            .default_value("24"))
+        .arg(Arg::new("anomaly-threshold")
+            .long("anomaly-threshold")
+            .value_name("Z")
+            .help("Robust z-score above which an hour is reported as anomalous (default: 3.5)")
+            .default_value("3.5"))
+        .args(record_args())
+        .arg(Arg::new("hashtag-min-posts")
+            .long("hashtag-min-posts")
+            .value_name("COUNT")
+            .help("Minimum posts for a hashtag to be ranked as happiest/saddest (default: 10)")
+            .default_value("10"))
+        .arg(Arg::new("trend-window")
+            .long("trend-window")
+            .value_name("HOURS")
+            .help("Length of the window in which hashtag bursts are detected (default: 6)")
+            .default_value("6"))
+        .arg(Arg::new("trend-baseline")
+            .long("trend-baseline")
+            .value_name("HOURS")
+            .help("Number of hours before the window used as the hashtag baseline (default: 24)")
+            .default_value("24"))
+        .arg(Arg::new("trend-min-posts")
+            .long("trend-min-posts")
+            .value_name("COUNT")
+            .help("Minimum posts in the window for a hashtag to be considered trending (default: 5)")
+            .value_parser(|value: &str| parse_positive_count(value))
+            .default_value("5"))
+        .arg(Arg::new("trend-threshold")
+            .long("trend-threshold")
+            .value_name("Z")
+            .help("Burst score above which a hashtag is reported as trending (default: 3.0)")
+            .default_value("3.0"))
+        .arg(Arg::new("instance-min-posts")
+            .long("instance-min-posts")
+            .value_name("COUNT")
+            .help("Minimum posts for an instance to be ranked as happiest/saddest (default: 10)")
+            .default_value("10"))
+        .arg(Arg::new("graph-export")
+            .long("graph-export")
+            .value_name("FORMAT")
+            .help("Build the mention/reply graph and export it as 'edgelist' (one file per rank) or 'graphml'")
+            .value_parser(["edgelist", "graphml"])
+            .required(false))
+        .arg(Arg::new("pagerank")
+            .long("pagerank")
+            .help("Rank accounts by PageRank on the interaction graph")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("pagerank-damping")
+            .long("pagerank-damping")
+            .value_name("FACTOR")
+            .help("PageRank damping factor (default: 0.85)")
+            .default_value("0.85"))
+        .arg(Arg::new("pagerank-iterations")
+            .long("pagerank-iterations")
+            .value_name("COUNT")
+            .help("Maximum number of PageRank iterations (default: 50)")
+            .default_value("50"))
+        .arg(Arg::new("pagerank-tolerance")
+            .long("pagerank-tolerance")
+            .value_name("DELTA")
+            .help("Stop PageRank once the L1 change between iterations drops below this (default: 1e-8)")
+            .default_value("1e-8"))
+        .arg(Arg::new("influence-top")
+            .long("influence-top")
+            .value_name("COUNT")
+            .help("Number of most influential accounts to report and rank by sentiment (default: 100)")
+            .default_value("100"))
+        .arg(Arg::new("threads")
+            .long("threads")
+            .help("Reassemble reply threads and report conversation-level sentiment")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("thread-min-size")
+            .long("thread-min-size")
+            .value_name("COUNT")
+            .help("Minimum posts for a thread to be ranked as most positive/negative (default: 3)")
+            .default_value("3"))
+        .arg(Arg::new("dedup")
+            .long("dedup")
+            .help("Remove repeated copies of a status (same URI or id) across all ranks before aggregation, keeping the latest edit")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("time-ordered")
+            .long("time-ordered")
+            .help("The file is (mostly) ordered by creation time: binary-search the --from/--to range instead of scanning the whole file")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("time-slack")
+            .long("time-slack")
+            .value_name("HOURS")
+            .help("How far out of order statuses may be around the --from/--to bounds with --time-ordered (default: 1)")
+            .default_value("1"))
+        .arg(Arg::new("group-by")
+            .long("group-by")
+            .value_name("DIMENSIONS")
+            .help("Also aggregate sentiment over comma-separated dimensions, e.g. hour,language or instance,day (repeatable), \
+                   written as CSV next to the built-in hour and user reports. \
+                   Dimensions: hour, day, month, weekday, hour_of_day, language, instance, user, hashtag, visibility, account")
+            .value_parser(|value: &str| parse_group_by(value))
+            .action(ArgAction::Append))
+        .arg(Arg::new("engagement-top")
+            .long("engagement-top")
+            .value_name("COUNT")
+            .help("Number of most engaged posts to report per sentiment class (default: 5)")
+            .default_value("5"))
+        .arg(Arg::new("words")
+            .long("words")
+            .help("Count words and bigrams and report those most associated with positive or negative sentiment")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("word-min-count")
+            .long("word-min-count")
+            .value_name("COUNT")
+            .help("Minimum uses for a word or bigram to be ranked and exported (default: 5)")
+            .default_value("5"))
+        .arg(Arg::new("quantiles")
+            .long("quantiles")
+            .help("Estimate sentiment quantiles and histograms overall, per hour and per user")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("quantile-min-posts")
+            .long("quantile-min-posts")
+            .value_name("COUNT")
+            .help("Minimum posts for a user's sentiment quantiles to be exported (default: 5)")
+            .default_value("5"))
+        .arg(Arg::new("approx-users")
+            .long("approx-users")
+            .help("Rank users with fixed-size Count-Min and Space-Saving sketches instead of exact per-user sums \
+                   (bounds memory; per-user quantiles and influential user sentiment are not available)")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("approx-users-capacity")
+            .long("approx-users-capacity")
+            .value_name("COUNT")
+            .help("Candidate users kept for each approximate ranking (default: 1000)")
+            .default_value("1000"))
+        .arg(Arg::new("approx-users-epsilon")
+            .long("approx-users-epsilon")
+            .value_name("FRACTION")
+            .help("Count-Min error as a fraction of the total positive or negative sentiment (default: 0.0001)")
+            .value_parser(|value: &str| parse_open_fraction(value))
+            .default_value("0.0001"))
+        .arg(Arg::new("approx-users-delta")
+            .long("approx-users-delta")
+            .value_name("PROBABILITY")
+            .help("Probability that an approximate user total exceeds its error bound (default: 0.01)")
+            .value_parser(|value: &str| parse_open_fraction(value))
+            .default_value("0.01"))
+        .subcommand_negates_reqs(true)
+        .subcommand(Command::new("extract-text")
+            .about("Write the clean text, link domains and custom emoji of every status as NDJSON")
+            .arg(Arg::new("data")
+                .short('d')
+                .long("data")
+                .value_name("FILE")
+                .help("Path to Mastodon NDJSON file")
+                .required(true))
+            .arg(Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("DIR")
+                .help("Output directory for the extracted_text.part-NNNNN.ndjson files")
+                .required(false))
+            .arg(Arg::new("buffer-size")
+                .long("buffer-size")
+                .value_name("SIZE")
+                .help("Buffer size in MB for processing chunks (default: 100)")
+                .default_value("100")))
+        .subcommand(Command::new("watch")
+            .about("Follow a growing NDJSON file or a directory of rotating files and keep the hour and user results current")
+            .arg(Arg::new("data")
+                .short('d')
+                .long("data")
+                .value_name("PATH")
+                .help("Mastodon NDJSON file, or directory whose .ndjson files are read oldest first")
+                .required(true))
+            .arg(Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("DIR")
+                .help("Output directory for results")
+                .required(false))
+            .arg(Arg::new("interval")
+                .long("interval")
+                .value_name("SECONDS")
+                .help("Minimum seconds between rewrites of the result files (default: 30)")
+                .default_value("30"))
+            .arg(Arg::new("poll")
+                .long("poll")
+                .value_name("SECONDS")
+                .help("Seconds between checks for new data (default: 1)")
+                .default_value("1"))
+            .arg(Arg::new("max-idle")
+                .long("max-idle")
+                .value_name("SECONDS")
+                .help("Write the results and stop after this many seconds without new data (default: run until killed)"))
+            .args(record_args()))
         .get_matches();
     
-    let data_file = matches.get_one::<String>("data").unwrap();
-    
     // Initialize config
     let config = Config::default();
     
+    if let Some(extract_matches) = matches.subcommand_matches("extract-text") {
+        let data_file = extract_matches.get_one::<String>("data").unwrap();
+        let output_dir = extract_matches.get_one::<String>("output")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(&config.output_dir));
+        let buffer_size: usize = extract_matches.get_one::<String>("buffer-size")
+            .unwrap()
+            .parse()
+            .unwrap_or(100);
+        
+        let (local_start, local_end, _) = setup_mpi_file_boundaries(data_file, rank, size);
+        let local_records = dump_extracted_text(data_file, local_start, local_end, buffer_size * 1024 * 1024, rank, &output_dir);
+        let total_records = all_reduce_sum(&world, local_records as f64) as usize;
+        if rank == 0 {
+            println!("Extracted text from {} statuses", total_records);
+            println!("Program runs in {:.2} seconds", start_time.elapsed().as_secs_f64());
+        }
+        return Ok(());
+    }
+    
+    if let Some(watch_matches) = matches.subcommand_matches("watch") {
+        let data_path = PathBuf::from(watch_matches.get_one::<String>("data").unwrap());
+        let output_dir = watch_matches.get_one::<String>("output")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(&config.output_dir));
+        let watch = WatchOptions {
+            interval: watch_matches.get_one::<String>("interval").unwrap().parse().unwrap_or(30.0),
+            poll: watch_matches.get_one::<String>("poll").unwrap().parse().unwrap_or(1.0),
+            max_idle: watch_matches.get_one::<String>("max-idle")
+                .map(|idle| idle.parse().expect("--max-idle must be a number of seconds")),
+        };
+        
+        // Tailing is sequential, so rank 0 does it alone
+        if rank == 0 {
+            if size > 1 {
+                println!("watch runs on rank 0 only; the other {} ranks exit", size - 1);
+            }
+            run_watch(&data_path, &output_dir, &watch, &record_options(watch_matches)?, TOP_N);
+            println!("Program runs in {:.2} seconds", start_time.elapsed().as_secs_f64());
+        }
+        return Ok(());
+    }
+    
+    let data_file = matches.get_one::<String>("data").unwrap();
+    
     // Get output directory from command line or config
     let output_dir = if let Some(output) = matches.get_one::<String>("output") {
         PathBuf::from(output)
@@ -618,18 +2241,211 @@ fn main() -> io::Result<()> {
         .unwrap_or(100);
     let buffer_size_bytes = buffer_size * 1024 * 1024;
     
+    // Memory limit in MB for the user and hour maps; beyond it they are spilled to disk
+    let memory_limit: Option<usize> = matches.get_one::<usize>("memory-limit").copied();
+    
+    let checkpoint_options = matches.get_one::<String>("checkpoint-dir").map(|dir| CheckpointOptions {
+        dir: PathBuf::from(dir),
+        interval: matches.get_one::<String>("checkpoint-interval").unwrap().parse().unwrap_or(600.0),
+        resume: matches.get_flag("resume"),
+    });
+    
+    let top_n = TOP_N;
+    
+    // Anomaly detection parameters
+    let anomaly_window: usize = matches.get_one::<String>("anomaly-window")
+        .unwrap()
+        .parse()
+        .unwrap_or(24);
+    let anomaly_threshold: f64 = matches.get_one::<String>("anomaly-threshold")
+        .unwrap()
+        .parse()
+        .unwrap_or(3.5);
+    
+    let graph_export = matches.get_one::<String>("graph-export").cloned();
+    let run_pagerank = matches.get_flag("pagerank");
+    let pagerank_params = PageRankParams {
+        damping: matches.get_one::<String>("pagerank-damping").unwrap().parse().unwrap_or(0.85),
+        max_iterations: matches.get_one::<String>("pagerank-iterations").unwrap().parse().unwrap_or(50),
+        tolerance: matches.get_one::<String>("pagerank-tolerance").unwrap().parse().unwrap_or(1e-8),
+    };
+    let influence_top: usize = matches.get_one::<String>("influence-top")
+        .unwrap()
+        .parse()
+        .unwrap_or(100);
+    let thread_min_size: usize = matches.get_one::<String>("thread-min-size")
+        .unwrap()
+        .parse()
+        .unwrap_or(3);
+    
+    let processing_options = ProcessingOptions {
+        build_graph: graph_export.is_some() || run_pagerank,
+        build_threads: matches.get_flag("threads"),
+        group_by: matches.get_many::<Vec<GroupDimension>>("group-by")
+            .map(|specs| specs.cloned().collect())
+            .unwrap_or_default(),
+        count_words: matches.get_flag("words"),
+        quantiles: matches.get_flag("quantiles"),
+        approx_users: matches.get_flag("approx-users").then(|| ApproxUserParams {
+            capacity: matches.get_one::<String>("approx-users-capacity").unwrap().parse().unwrap_or(1000).max(1),
+            epsilon: *matches.get_one::<f64>("approx-users-epsilon").unwrap(),
+            delta: *matches.get_one::<f64>("approx-users-delta").unwrap(),
+        }),
+        engagement_top: matches.get_one::<String>("engagement-top").unwrap().parse().unwrap_or(5),
+        stopwords: bundled_stopwords(),
+        memory_bounded: memory_limit.is_some(),
+        ..record_options(&matches)?
+    };
+    
+    let hashtag_min_posts: usize = matches.get_one::<String>("hashtag-min-posts")
+        .unwrap()
+        .parse()
+        .unwrap_or(10);
+    
+    // Trend detection parameters
+    let trend_window: usize = matches.get_one::<String>("trend-window")
+        .unwrap()
+        .parse()
+        .unwrap_or(6);
+    let trend_baseline: usize = matches.get_one::<String>("trend-baseline")
+        .unwrap()
+        .parse()
+        .unwrap_or(24);
+    let trend_min_posts = *matches.get_one::<usize>("trend-min-posts").unwrap();
+    let trend_threshold: f64 = matches.get_one::<String>("trend-threshold")
+        .unwrap()
+        .parse()
+        .unwrap_or(3.0);
+    
+    let instance_min_posts: usize = matches.get_one::<String>("instance-min-posts")
+        .unwrap()
+        .parse()
+        .unwrap_or(10);
+    
+    let dedup = matches.get_flag("dedup");
+    
+    let word_min_count: usize = matches.get_one::<String>("word-min-count")
+        .unwrap()
+        .parse()
+        .unwrap_or(5);
+    let quantile_min_posts: usize = matches.get_one::<String>("quantile-min-posts")
+        .unwrap()
+        .parse()
+        .unwrap_or(5);
+    
     if rank == 0 {
         fs::create_dir_all(&output_dir).expect("Failed to create output directory");
         dump_num_processor(size);
     }
     
-    // Set up file boundaries for MPI
-    let (local_start, local_end, _) = setup_mpi_file_boundaries(data_file, rank, size);
+    // Set up file boundaries for MPI, restricted to the requested time range when the file is ordered
+    let time_bounded = processing_options.from.is_some() || processing_options.to.is_some();
+    let state_file = matches.get_one::<String>("state").map(PathBuf::from);
+    let incremental = state_file.as_ref().map(|state_file| {
+        broadcast_from_root(&world, rank, size, || start_incremental(state_file, data_file))
+    });
+    let (range_start, range_end) = if let Some(incremental) = &incremental {
+        if rank == 0 {
+            if let Some(reason) = &incremental.discarded {
+                println!("Ignoring state {}: it {}", state_file.as_ref().unwrap().display(), reason);
+            }
+            if let Some(previous) = &incremental.previous {
+                println!(
+                    "Continuing after byte {} of {} from {} earlier runs ({} new bytes)",
+                    previous.processed.len,
+                    data_file,
+                    previous.runs,
+                    incremental.processed.len - previous.processed.len
+                );
+            }
+        }
+        incremental.range
+    } else if time_bounded && matches.get_flag("time-ordered") {
+        let slack_hours: i64 = matches.get_one::<String>("time-slack")
+            .unwrap()
+            .parse()
+            .unwrap_or(1);
+        let (range_start, range_end) = locate_time_range(
+            data_file,
+            processing_options.from,
+            processing_options.to,
+            Duration::hours(slack_hours),
+        );
+        if rank == 0 {
+            println!("Time range located at bytes {}..{}", range_start, range_end);
+        }
+        (range_start, range_end)
+    } else {
+        (0, fs::metadata(data_file).expect("Failed to get file metadata").size())
+    };
+    let (local_start, local_end) = setup_mpi_range_boundaries(range_start, range_end, rank, size);
+    
+    // Every rank's byte range, which a resumed run replaces by the ranges its checkpoint left unscanned
+    let fresh_plan: Vec<Vec<ScanSegment>> = (0..size)
+        .map(|other| {
+            let (start, end) = setup_mpi_range_boundaries(range_start, range_end, other, size);
+            vec![ScanSegment::new(start, end)]
+        })
+        .collect();
+    let (checkpointer, plan, initial_state) = match &checkpoint_options {
+        Some(options) => {
+            let (checkpointer, plan, state) = start_checkpointing(&world, rank, size, options, data_file, fresh_plan.clone());
+            (Some(checkpointer), plan, state)
+        }
+        None => (None, fresh_plan.clone(), ScanState::default()),
+    };
+    
+    // Find the lines that repeat a status kept elsewhere
+    let duplicate_offsets = if dedup {
+        let dedup_start = Instant::now();
+        let offsets = find_duplicate_offsets(
+            &world,
+            rank,
+            size,
+            data_file,
+            (local_start, local_end),
+            buffer_size_bytes,
+            processing_options.reblog_mode,
+        );
+        dump_time(rank as i32, "deduplication", dedup_start.elapsed().as_secs_f64());
+        if plan == fresh_plan {
+            offsets
+        } else {
+            // Hand each duplicate to the rank that now scans its line
+            let mut outgoing: Vec<Vec<u64>> = vec![Vec::new(); size];
+            for offset in offsets {
+                if let Some(owner) = segment_owner(&plan, offset) {
+                    outgoing[owner].push(offset);
+                }
+            }
+            exchange_between_ranks(&world, rank, size, outgoing).into_iter().flatten().collect()
+        }
+    } else {
+        HashSet::new()
+    };
     
     // Process the data
     let processing_start = Instant::now();
-    let (local_hour_sentiment, local_user_sentiment, local_lines_processed) = 
-        process_chunk_memory_mapped(data_file, local_start, local_end, buffer_size_bytes);
+    let mut scan = ScanProgress {
+        segments: plan[rank].clone(),
+        state: initial_state,
+        spill: memory_limit.map(|limit| {
+            SpillState::new(config.resolve_path("log_dir", "tmp"), rank, limit * 1024 * 1024)
+        }),
+        checkpointer,
+    };
+    process_chunk_memory_mapped(data_file, buffer_size_bytes, &processing_options, &duplicate_offsets, &mut scan);
+    if let Some(checkpointer) = scan.checkpointer.as_mut() {
+        checkpointer.write(&scan.segments, &scan.state);
+    }
+    let ScanState {
+        aggregates: mut local_aggregates,
+        partitioned: local_partitioned,
+        lines_processed: local_lines_processed,
+    } = scan.state;
+    if let Some(spill) = scan.spill {
+        finish_external_aggregation(&world, rank, size, spill, &mut local_aggregates, top_n);
+    }
     let processing_time = processing_start.elapsed().as_secs_f64();
     
     dump_time(rank as i32, "data processing", processing_time);
@@ -638,33 +2454,8 @@ fn main() -> io::Result<()> {
     world.barrier();
     
     // Gather results from all processes
-    let gathering_start = Instant::now();
-    
-    // Serialize and gather hour sentiment dictionaries
-    let mut all_hour_dicts = Vec::new();
-    if rank == 0 {
-        all_hour_dicts.push(local_hour_sentiment.clone());
-        for i in 1..size {
-            let from_rank = i as i32;
-            let hour_dict: HashMap<String, f64> = world.process_at_rank(from_rank).receive().0;
-            all_hour_dicts.push(hour_dict);
-        }
-    } else {
-        world.process_at_rank(0).send(&local_hour_sentiment);
-    }
-    
-    // Serialize and gather user sentiment dictionaries
-    let mut all_user_dicts = Vec::new();
-    if rank == 0 {
-        all_user_dicts.push(local_user_sentiment.clone());
-        for i in 1..size {
-            let from_rank = i as i32;
-            let user_dict: HashMap<String, (String, f64)> = world.process_at_rank(from_rank).receive().0;
-            all_user_dicts.push(user_dict);
-        }
-    } else {
-        world.process_at_rank(0).send(&local_user_sentiment);
-    }
+    // Serialize and gather the per-rank aggregates
+    let all_aggregates = gather_at_root(&world, rank, size, local_aggregates);
     
     // Gather total processed lines
     let mut total_lines = local_lines_processed;
@@ -678,36 +2469,483 @@ fn main() -> io::Result<()> {
         world.process_at_rank(0).send(&local_lines_processed);
     }
     
-    let gathering_time = gathering_start.elapsed().as_secs_f64();
+    // Distribute, rank and export the interaction graph
+    let mut influential_users = Vec::new();
+    let mut influential_labels = HashMap::new();
+    if processing_options.build_graph {
+        let graph_start = Instant::now();
+        let graph_partition = partition_graph(&world, rank, size, local_partitioned.graph);
+        
+        if run_pagerank {
+            let (scores, iterations) = distributed_pagerank(&world, rank, size, &graph_partition, pagerank_params);
+            
+            // Each rank contributes its own top accounts; the global top is among them
+            let local_top: Vec<(String, f64, Option<String>)> = top_n_by_value(&scores, influence_top, true)
+                .into_iter()
+                .map(|(user_id, score)| {
+                    let label = graph_partition.labels.get(&user_id).cloned();
+                    (user_id, score, label)
+                })
+                .collect();
+            let mut candidates: Vec<(String, f64, Option<String>)> =
+                gather_at_root(&world, rank, size, local_top).into_iter().flatten().collect();
+            if rank == 0 {
+                println!("PageRank finished after {} iterations", iterations);
+                candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then_with(|| a.0.cmp(&b.0)));
+                candidates.truncate(influence_top);
+                for (user_id, score, label) in candidates {
+                    if let Some(label) = label {
+                        influential_labels.insert(user_id.clone(), label);
+                    }
+                    influential_users.push((user_id, score));
+                }
+            }
+        }
+        
+        match graph_export.as_deref() {
+            Some("edgelist") => dump_graph_edge_list(&graph_partition, rank, &output_dir),
+            Some("graphml") => {
+                let partitions = gather_at_root(&world, rank, size, graph_partition);
+                if rank == 0 {
+                    let mut graph = InteractionGraph::default();
+                    for partition in partitions {
+                        graph.merge(partition);
+                    }
+                    println!("Interaction graph has {} edges", graph.edge_count());
+                    dump_graph_graphml(&graph, &output_dir);
+                }
+            }
+            _ => {}
+        }
+        
+        dump_time(rank as i32, "interaction graph", graph_start.elapsed().as_secs_f64());
+    }
+    
+    // Reassemble reply threads on rank 0
+    let mut threads = Vec::new();
+    let mut unresolved_parents = 0;
+    if processing_options.build_threads {
+        let threads_start = Instant::now();
+        let conversation_nodes = collect_conversation_nodes(&world, rank, size, local_partitioned.thread_nodes);
+        if rank == 0 {
+            unresolved_parents = count_unresolved_parents(&conversation_nodes);
+            threads = build_threads(conversation_nodes);
+        }
+        dump_time(rank as i32, "thread reconstruction", threads_start.elapsed().as_secs_f64());
+    }
     
     // Process the gathered data on rank 0
     if rank == 0 {
-        let merging_start = Instant::now();
-        
-        // Merge dictionaries
-        let global_hour_sentiment = merge_hour_dicts(all_hour_dicts);
-        let global_user_sentiment = merge_user_dicts(all_user_dicts);
-        
-        let merging_time = merging_start.elapsed().as_secs_f64();
+        // Merge dictionaries, and with them the results of earlier runs over the same file
+        let mut global_aggregates = merge_aggregates(all_aggregates);
+        if let (Some(state_file), Some(IncrementalStart { previous: Some(_), .. })) = (&state_file, &incremental) {
+            global_aggregates = merge_aggregates(vec![read_state_aggregates(state_file), global_aggregates]);
+        }
+        let global_hour_sentiment = &global_aggregates.hour_sentiment;
+        let global_user_sentiment = &global_aggregates.user_sentiment;
         
         // Find top N items
-        let top_n = 5;
-        let happiest_hours = top_n_by_value(&global_hour_sentiment, top_n, true);
-        let saddest_hours = top_n_by_value(&global_hour_sentiment, top_n, false);
-        let happiest_users = top_n_users(&global_user_sentiment, top_n, true);
-        let saddest_users = top_n_users(&global_user_sentiment, top_n, false);
+        let happiest_hours = top_n_by_value(global_hour_sentiment, top_n, true);
+        let saddest_hours = top_n_by_value(global_hour_sentiment, top_n, false);
+        let (happiest_users, saddest_users) = match &global_aggregates.approx_users {
+            Some(approx) => {
+                let happiest = approx.ranking(top_n, true);
+                let saddest = approx.ranking(top_n, false);
+                dump_approx_users(approx, &happiest, &saddest, &output_dir);
+                let exact_form = |users: Vec<ApproxUserTotal>| -> Vec<(String, (String, f64))> {
+                    users.into_iter().map(|user| (user.user_id, (user.username, user.estimate))).collect()
+                };
+                (exact_form(happiest), exact_form(saddest))
+            }
+            None => (top_n_users(global_user_sentiment, top_n, true), top_n_users(global_user_sentiment, top_n, false)),
+        };
+        let (series_hours, implausible_posts) = plausible_hours(&global_aggregates.hour_count);
+        let hour_anomalies = detect_hour_anomalies(
+            global_hour_sentiment,
+            &global_aggregates.hour_count,
+            &series_hours,
+            anomaly_window,
+            anomaly_threshold,
+        );
         
         // Output results
         dump_happiest_hours(&happiest_hours, &output_dir);
         dump_saddest_hours(&saddest_hours, &output_dir);
         dump_happiest_users(&happiest_users, &output_dir);
         dump_saddest_users(&saddest_users, &output_dir);
+        dump_hour_anomalies(&hour_anomalies, implausible_posts, top_n, &output_dir);
+        dump_language_sentiment(&global_aggregates.language_stats, &output_dir);
+        dump_language_hours(
+            &global_aggregates.language_stats,
+            &global_aggregates.language_hour_sentiment,
+            top_n,
+            &output_dir,
+        );
+        dump_category_sentiment(&global_aggregates.category_stats, &output_dir);
+        dump_distinct_users(
+            &global_aggregates.all_users,
+            &global_aggregates.hour_users,
+            &global_aggregates.day_users,
+            &output_dir,
+        );
+        if processing_options.quantiles {
+            dump_sentiment_distribution(
+                &global_aggregates.sentiment_digest,
+                &global_aggregates.hour_digests,
+                &global_aggregates.user_digests,
+                global_user_sentiment,
+                quantile_min_posts,
+                &output_dir,
+            );
+        }
+        for dimensions in &processing_options.group_by {
+            let groups = global_aggregates.grouped.get(&group_by_name(dimensions)).cloned().unwrap_or_default();
+            dump_grouped_table(dimensions, &groups, &output_dir);
+        }
+        
+        let hashtag_means = group_mean_sentiment(&global_aggregates.hashtag_stats, hashtag_min_posts);
+        dump_group_ranking(
+            "Top Happiest Hashtags",
+            "happiest_hashtags.txt",
+            "#",
+            &top_n_by_value(&hashtag_means, top_n, true),
+            &global_aggregates.hashtag_stats,
+            &output_dir,
+        );
+        dump_group_ranking(
+            "Top Saddest Hashtags",
+            "saddest_hashtags.txt",
+            "#",
+            &top_n_by_value(&hashtag_means, top_n, false),
+            &global_aggregates.hashtag_stats,
+            &output_dir,
+        );
+        dump_group_table("hashtag_sentiment.csv", "hashtag", &global_aggregates.hashtag_stats, &output_dir);
+        dump_group_hourly("hashtag_hourly.csv", "hashtag", &global_aggregates.hashtag_hour_stats, &output_dir);
+        
+        let trending_hashtags = detect_trending_hashtags(
+            &global_aggregates.hashtag_hour_count,
+            &global_aggregates.hour_count,
+            &series_hours,
+            trend_window,
+            trend_baseline,
+            trend_min_posts,
+            trend_threshold,
+        );
+        dump_trending_hashtags(&trending_hashtags, trend_window, top_n, &output_dir);
+        
+        let instance_means = group_mean_sentiment(&global_aggregates.instance_stats, instance_min_posts);
+        dump_group_ranking(
+            "Top Happiest Instances",
+            "happiest_instances.txt",
+            "",
+            &top_n_by_value(&instance_means, top_n, true),
+            &global_aggregates.instance_stats,
+            &output_dir,
+        );
+        dump_group_ranking(
+            "Top Saddest Instances",
+            "saddest_instances.txt",
+            "",
+            &top_n_by_value(&instance_means, top_n, false),
+            &global_aggregates.instance_stats,
+            &output_dir,
+        );
+        dump_group_table("instance_sentiment.csv", "instance", &global_aggregates.instance_stats, &output_dir);
+        dump_group_hourly("instance_hourly.csv", "instance", &global_aggregates.instance_hour_stats, &output_dir);
+        
+        dump_engagement_by_sentiment(&global_aggregates.engagement_histogram, &output_dir);
+        let engagement_classes = [
+            ("positive", "Most Engaged Positive Posts"),
+            ("neutral", "Most Engaged Neutral Posts"),
+            ("negative", "Most Engaged Negative Posts"),
+        ];
+        for (class, title) in engagement_classes {
+            let posts = global_aggregates.top_engaged.get(class).map(Vec::as_slice).unwrap_or_default();
+            let posts = &posts[..min(posts.len(), processing_options.engagement_top)];
+            dump_engaged_posts(title, &format!("most_engaged_{}_posts.txt", class), posts, &output_dir);
+        }
+        dump_hour_engagement(
+            &global_aggregates.hour_engagement,
+            global_hour_sentiment,
+            &global_aggregates.hour_count,
+            &output_dir,
+        );
+        
+        if processing_options.count_words {
+            let word_associations = term_associations(&global_aggregates.word_counts, word_min_count);
+            let bigram_associations = term_associations(&global_aggregates.bigram_counts, word_min_count);
+            dump_term_ranking(
+                "Words Most Associated with Positive Sentiment",
+                "positive_words.txt",
+                &top_n_associations(&word_associations, top_n, true),
+                &output_dir,
+            );
+            dump_term_ranking(
+                "Words Most Associated with Negative Sentiment",
+                "negative_words.txt",
+                &top_n_associations(&word_associations, top_n, false),
+                &output_dir,
+            );
+            dump_term_ranking(
+                "Bigrams Most Associated with Positive Sentiment",
+                "positive_bigrams.txt",
+                &top_n_associations(&bigram_associations, top_n, true),
+                &output_dir,
+            );
+            dump_term_ranking(
+                "Bigrams Most Associated with Negative Sentiment",
+                "negative_bigrams.txt",
+                &top_n_associations(&bigram_associations, top_n, false),
+                &output_dir,
+            );
+            dump_term_table("word_frequencies.csv", "word", &word_associations, &output_dir);
+            dump_term_table("bigram_frequencies.csv", "bigram", &bigram_associations, &output_dir);
+        }
+        
+        if processing_options.scorer.is_some() {
+            dump_sentiment_comparison(&global_aggregates.sentiment_comparison, &output_dir);
+        }
+        
+        if processing_options.reblog_mode == ReblogMode::Separate {
+            dump_most_reblogged_authors(&global_aggregates.reblogged_authors, top_n, &output_dir);
+        }
+        
+        if run_pagerank {
+            dump_influential_users(&influential_users, global_user_sentiment, &influential_labels, top_n, &output_dir);
+            
+            // Happiest and saddest users among the accounts that actually reach people
+            let influential_sentiment: HashMap<String, (String, f64)> = influential_users
+                .iter()
+                .filter_map(|(user_id, _)| global_user_sentiment.get(user_id).map(|entry| (user_id.clone(), entry.clone())))
+                .collect();
+            dump_user_ranking(
+                "Top Happiest Influential Users",
+                "happiest_influential_users.txt",
+                &top_n_users(&influential_sentiment, top_n, true),
+                &output_dir,
+            );
+            dump_user_ranking(
+                "Top Saddest Influential Users",
+                "saddest_influential_users.txt",
+                &top_n_users(&influential_sentiment, top_n, false),
+                &output_dir,
+            );
+        }
+        
+        if processing_options.build_threads {
+            let mut ranked_threads: Vec<&ThreadMetrics> =
+                threads.iter().filter(|thread| thread.size >= thread_min_size).collect();
+            ranked_threads.sort_by(|a, b| a.mean_sentiment.partial_cmp(&b.mean_sentiment).unwrap_or(Ordering::Equal));
+            let most_negative: Vec<&ThreadMetrics> = ranked_threads.iter().take(top_n).copied().collect();
+            let most_positive: Vec<&ThreadMetrics> = ranked_threads.iter().rev().take(top_n).copied().collect();
+            
+            println!("Reassembled {} reply threads", threads.len());
+            println!("Replies whose parent is not in the dump: {}", unresolved_parents);
+            if global_aggregates.unlinked_replies > 0 {
+                println!("Replies without an id to link them to their thread: {}", global_aggregates.unlinked_replies);
+            }
+            dump_thread_ranking("Most Positive Threads", "most_positive_threads.txt", &most_positive, &output_dir);
+            dump_thread_ranking("Most Negative Threads", "most_negative_threads.txt", &most_negative, &output_dir);
+            dump_thread_table(&threads, &output_dir);
+        }
         
         let total_time = start_time.elapsed().as_secs_f64();
         println!("Total processing time: {:.2} seconds", total_time);
         println!("Total lines processed: {}", total_lines);
+        if processing_options.languages.is_some() {
+            println!("Statuses excluded by --language: {}", global_aggregates.language_filtered);
+        }
+        if processing_options.exclude_bots
+            || processing_options.exclude_sensitive
+            || processing_options.visibilities.is_some()
+        {
+            println!("Statuses excluded by category filters: {}", global_aggregates.category_filtered);
+        }
+        if time_bounded {
+            println!("Statuses outside --from/--to: {}", global_aggregates.time_filtered);
+        }
+        if processing_options.filter.is_some() {
+            println!("Statuses excluded by --where: {}", global_aggregates.expression_filtered);
+        }
+        if dedup {
+            println!("Duplicate statuses removed: {}", global_aggregates.duplicates_skipped);
+        }
+        if memory_limit.is_some() {
+            println!("Spill files written: {}", global_aggregates.spill_files);
+        }
+        match processing_options.reblog_mode {
+            ReblogMode::Skip => println!("Reblogs skipped: {}", global_aggregates.reblogs_skipped),
+            ReblogMode::Separate => println!(
+                "Reblogs counted separately: {} with mean sentiment {:+.4}",
+                global_aggregates.reblog_stats.count,
+                global_aggregates.reblog_stats.mean()
+            ),
+            _ => {}
+        }
+        if let (Some(state_file), Some(incremental)) = (&state_file, &incremental) {
+            write_state(state_file, data_file, incremental, total_lines, &global_aggregates);
+            println!("State saved to {} after byte {}", state_file.display(), incremental.processed.len);
+        }
+        if let Some(options) = &checkpoint_options {
+            remove_checkpoints(&options.dir);
+        }
         println!("Program runs in {:.2} seconds", total_time);
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpi::environment::Universe;
+    use serde_json::json;
+    use std::sync::{Mutex, MutexGuard, OnceLock};
+    
+    // MPI can be initialised once per process, so tests that need a communicator share one
+    // single-rank universe and take turns using it
+    pub(crate) fn mpi_universe() -> MutexGuard<'static, Universe> {
+        static UNIVERSE: OnceLock<Mutex<Universe>> = OnceLock::new();
+        UNIVERSE
+            .get_or_init(|| {
+                let (universe, _) = mpi::initialize_with_threading(mpi::Threading::Serialized)
+                    .expect("Failed to initialize MPI");
+                Mutex::new(universe)
+            })
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    // Scratch directory for a test, removed and recreated on every run
+    pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mastodon-analytics-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+    
+    // One line of an Elasticsearch export, shaped like data/mastodon-106k.ndjson, with `fields`
+    // merged into the status
+    pub(crate) fn exported_status(fields: Value) -> String {
+        let mut status = json!({
+            "sensitive": false,
+            "createdAt": "2025-01-30T11:55:33.000Z",
+            "content": "<p>Hello</p>",
+            "sentiment": 0.25,
+            "favouritesCount": 0,
+            "url": "https://mastodon.social/@alice/113917174294487934",
+            "mentions": [],
+            "inReplyToId": null,
+            "tags": [],
+            "visibility": "public",
+            "inReplyToAccountId": null,
+            "repliesCount": 0,
+            "reblog": null,
+            "spoilerText": "",
+            "account": {
+                "bot": false,
+                "id": "109491602528257114",
+                "url": "https://mastodon.social/@alice",
+                "username": "alice",
+                "group": false,
+                "acct": "alice@mastodon.social",
+                "followersCount": 340
+            },
+            "language": "en",
+            "reblogsCount": 0,
+            "emojis": [],
+            "uri": "https://mastodon.social/users/alice/statuses/113917174294487934"
+        });
+        for (key, value) in fields.as_object().expect("fields should be an object") {
+            status[key] = value.clone();
+        }
+        json!({"doc": status, "@version": "1", "@timestamp": "2025-02-07T09:30:15.916537285Z", "doc_as_upsert": true})
+            .to_string()
+    }
+    
+    #[test]
+    fn top_n_keeps_the_largest_and_smallest_values() {
+        let hours: HashMap<String, f64> = (0..20).map(|i| (format!("h{:02}", i), (i as f64 - 10.0) / 10.0)).collect();
+        assert_eq!(
+            top_n_by_value(&hours, 3, true),
+            [("h19".to_string(), 0.9), ("h18".to_string(), 0.8), ("h17".to_string(), 0.7)]
+        );
+        assert_eq!(
+            top_n_by_value(&hours, 2, false),
+            [("h00".to_string(), -1.0), ("h01".to_string(), -0.9)]
+        );
+        
+        let users: HashMap<String, (String, f64)> = (0..20)
+            .map(|i| (i.to_string(), (format!("user{}", i), i as f64)))
+            .collect();
+        let happiest: Vec<_> = top_n_users(&users, 2, true).into_iter().map(|(_, (name, total))| (name, total)).collect();
+        assert_eq!(happiest, [("user19".to_string(), 19.0), ("user18".to_string(), 18.0)]);
+        let saddest: Vec<_> = top_n_users(&users, 2, false).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(saddest, ["0", "1"]);
+    }
+    
+    #[test]
+    fn exported_statuses_are_read_from_the_doc_envelope() {
+        let line = exported_status(json!({}));
+        let status = MastodonData::from_json_str(&line).unwrap();
+        assert_eq!(status.created_at.as_deref(), Some("2025-01-30T11:55:33.000Z"));
+        assert_eq!(status.user_id.as_deref(), Some("109491602528257114"));
+        assert_eq!(status.sentiment, Some(0.25));
+        
+        let mut aggregates = Aggregates::default();
+        let mut partitioned = PartitionedData::default();
+        processing_data(&line, &mut aggregates, &mut partitioned, &ProcessingOptions::default());
+        assert_eq!(aggregates.hour_count.get("2025-01-30 11"), Some(&1));
+        assert_eq!(aggregates.user_sentiment.get("109491602528257114"), Some(&("alice".to_string(), 0.25)));
+    }
+    
+    // Aggregates of `lines` processed with `options`, as one rank would collect them
+    pub(crate) fn aggregate(lines: &[String], options: &ProcessingOptions) -> (Aggregates, PartitionedData) {
+        let mut aggregates = Aggregates::default();
+        let mut partitioned = PartitionedData::default();
+        for line in lines {
+            processing_data(line, &mut aggregates, &mut partitioned, options);
+        }
+        (aggregates, partitioned)
+    }
+    
+    // Hashtags in the shape Mastodon exports them
+    pub(crate) fn tags(names: &[&str]) -> Value {
+        names
+            .iter()
+            .map(|name| json!({"name": name, "url": format!("https://mastodon.social/tags/{}", name)}))
+            .collect()
+    }
+    
+    #[test]
+    fn sketch_error_parameters_must_be_open_fractions() {
+        for value in ["0", "1", "-0.1", "1.5", "abc", "NaN"] {
+            assert!(parse_open_fraction(value).is_err(), "{} should be rejected", value);
+        }
+        assert_eq!(parse_open_fraction("0.0001"), Ok(0.0001));
+        assert_eq!(parse_open_fraction("0.99"), Ok(0.99));
+    }
+    
+    #[test]
+    fn adjacent_ranges_see_every_line_once() {
+        let lines: Vec<String> = (0..40).map(|i| format!("{{\"n\":{},\"pad\":\"{}\"}}", i, "x".repeat(i * 7 % 11))).collect();
+        let dir = scratch_dir("chunk-lines");
+        let path = dir.join("lines.ndjson");
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        let path = path.to_str().unwrap();
+        
+        // Cut the file at every byte, including inside lines and on their newlines
+        for cut in 0..=len {
+            for buffer in [8, 64, 1 << 20] {
+                let mut seen = Vec::new();
+                for (start, end) in [(0, cut), (cut, len)] {
+                    for_each_chunk_line(path, start, end, buffer, |_, line| seen.push(line.to_string()));
+                }
+                assert_eq!(seen, lines, "cut at {} with {}-byte segments", cut, buffer);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+//! Distributed PageRank over the interaction graph
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use mpi::traits::*;
+use crate::{all_reduce_sum, exchange_between_ranks, owner_rank, print_ranking, signed_score, user_ranking_lines, write_ranking, InteractionGraph};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PageRankParams {
+    pub(crate) damping: f64,
+    pub(crate) max_iterations: usize,
+    pub(crate) tolerance: f64,
+}
+
+// Weighted PageRank over the partitioned graph. Each rank owns the scores of the nodes that
+// hash to it, pushes its sources' contributions to the owners of their targets every
+// iteration, and spreads the mass of dangling nodes uniformly. Returns this rank's scores.
+pub(crate) fn distributed_pagerank<C: Communicator>(
+    world: &C,
+    rank: usize,
+    size: usize,
+    graph: &InteractionGraph,
+    params: PageRankParams,
+) -> (HashMap<String, f64>, usize) {
+    // Tell the owner of every target that the node exists
+    let mut announced: Vec<HashSet<String>> = (0..size).map(|_| HashSet::new()).collect();
+    for targets in graph.edges.values() {
+        for target in targets.keys() {
+            announced[owner_rank(target, size)].insert(target.clone());
+        }
+    }
+    let mut owned: HashSet<String> = graph.edges.keys().cloned().collect();
+    for nodes in exchange_between_ranks(world, rank, size, announced) {
+        owned.extend(nodes);
+    }
+    
+    let node_count = all_reduce_sum(world, owned.len() as f64);
+    if node_count == 0.0 {
+        return (HashMap::new(), 0);
+    }
+    
+    let out_weight: HashMap<&String, f64> = graph.edges
+        .iter()
+        .map(|(source, targets)| (source, targets.values().map(|edge| edge.weight() as f64).sum()))
+        .collect();
+    let mut scores: HashMap<String, f64> = owned.into_iter().map(|node| (node, 1.0 / node_count)).collect();
+    
+    let mut iterations = 0;
+    while iterations < params.max_iterations {
+        iterations += 1;
+        
+        let mut contributions: Vec<HashMap<String, f64>> = (0..size).map(|_| HashMap::new()).collect();
+        let mut local_dangling = 0.0;
+        for (node, score) in &scores {
+            match (graph.edges.get(node), out_weight.get(node)) {
+                (Some(targets), Some(&total)) if total > 0.0 => {
+                    for (target, edge) in targets {
+                        *contributions[owner_rank(target, size)].entry(target.clone()).or_insert(0.0) +=
+                            params.damping * score * edge.weight() as f64 / total;
+                    }
+                }
+                _ => local_dangling += score,
+            }
+        }
+        
+        let dangling = all_reduce_sum(world, local_dangling);
+        let base = (1.0 - params.damping) / node_count + params.damping * dangling / node_count;
+        let mut new_scores: HashMap<String, f64> = scores.keys().map(|node| (node.clone(), base)).collect();
+        for received in exchange_between_ranks(world, rank, size, contributions) {
+            for (node, contribution) in received {
+                *new_scores.entry(node).or_insert(base) += contribution;
+            }
+        }
+        
+        let local_delta: f64 = new_scores
+            .iter()
+            .map(|(node, score)| (score - scores.get(node).copied().unwrap_or(0.0)).abs())
+            .sum();
+        scores = new_scores;
+        if all_reduce_sum(world, local_delta) < params.tolerance {
+            break;
+        }
+    }
+    
+    (scores, iterations)
+}
+
+pub(crate) fn dump_influential_users(
+    influential: &[(String, f64)],
+    user_sentiment: &HashMap<String, (String, f64)>,
+    graph_labels: &HashMap<String, String>,
+    print_limit: usize,
+    output_dir: &Path,
+) {
+    let lines: Vec<String> = influential
+        .iter()
+        .enumerate()
+        .map(|(i, (user_id, score))| {
+            let (name, sentiment) = match user_sentiment.get(user_id) {
+                Some((username, sentiment)) => (username.as_str(), signed_score(*sentiment)),
+                None => (graph_labels.get(user_id).map(String::as_str).unwrap_or(user_id), "n/a".to_string()),
+            };
+            format!("{}. {} (ID: {}) with PageRank {:.6} and total sentiment {}", i + 1, name, user_id, score, sentiment)
+        })
+        .collect();
+    print_ranking("Most Influential Users", &lines[..lines.len().min(print_limit)]);
+    write_ranking("Most Influential Users", "influential_users.txt", &lines, output_dir);
+}
+
+pub(crate) fn dump_user_ranking(title: &str, file_name: &str, users: &[(String, (String, f64))], output_dir: &Path) {
+    let lines = user_ranking_lines(users, signed_score);
+    print_ranking(title, &lines);
+    write_ranking(title, file_name, &lines, output_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{mpi_universe, scratch_dir};
+    use crate::top_n_by_value;
+    use std::fs;
+    
+    #[test]
+    fn pagerank_ranks_the_most_mentioned_account_first() {
+        // Ten accounts mention "hub"; "hub" passes its rank on to "relay", which mentions nobody
+        let mut graph = InteractionGraph::default();
+        for i in 0..10 {
+            graph.add_interaction(&format!("fan{}", i), "hub", false, 0.1);
+        }
+        graph.add_interaction("hub", "relay", false, 0.0);
+        
+        let universe = mpi_universe();
+        let world = universe.world();
+        let params = PageRankParams { damping: 0.85, max_iterations: 100, tolerance: 1e-10 };
+        let (scores, iterations) = distributed_pagerank(&world, 0, 1, &graph, params);
+        drop(universe);
+        
+        assert_eq!(scores.len(), 12);
+        assert!(iterations < 100);
+        assert!((scores.values().sum::<f64>() - 1.0).abs() < 1e-9);
+        let ranking = top_n_by_value(&scores, 2, true);
+        assert_eq!(ranking[0].0, "hub");
+        assert_eq!(ranking[1].0, "relay");
+        assert!(scores["hub"] > 5.0 * scores["fan0"]);
+        
+        // Every ranked account is written, not just the ones printed
+        let output_dir = scratch_dir("influence");
+        let influential: Vec<(String, f64)> = top_n_by_value(&scores, 8, true);
+        dump_influential_users(&influential, &HashMap::new(), &graph.labels, 2, &output_dir);
+        let written = fs::read_to_string(output_dir.join("influential_users.txt")).unwrap();
+        assert_eq!(written.lines().count(), 2 + 8);
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+//! Counting of reblogs and the report on reblogged authors
+
+use std::collections::HashMap;
+use std::path::Path;
+use crate::{top_n_by_value, GroupStats};
+use crate::groups::dump_group_ranking;
+
+// How statuses that boost another status are counted
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum ReblogMode {
+    // Count the reblog as a status of the account that boosted it
+    #[default]
+    Include,
+    // Drop reblogs entirely
+    Skip,
+    // Count the reblogged status once, for its original author
+    Original,
+    // Keep reblogs out of the main aggregates and report them on their own
+    Separate,
+}
+
+impl ReblogMode {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "include" => Some(ReblogMode::Include),
+            "skip" => Some(ReblogMode::Skip),
+            "original" => Some(ReblogMode::Original),
+            "separate" => Some(ReblogMode::Separate),
+            _ => None,
+        }
+    }
+}
+
+// Authors whose statuses were boosted most often under `--reblogs separate`, with the mean
+// sentiment of those boosts
+pub(crate) fn dump_most_reblogged_authors(reblogged_authors: &HashMap<String, GroupStats>, top_n: usize, output_dir: &Path) {
+    let reblog_counts: HashMap<String, f64> = reblogged_authors
+        .iter()
+        .map(|(author, group)| (author.clone(), group.stats.count as f64))
+        .collect();
+    let most_reblogged: Vec<(String, f64)> = top_n_by_value(&reblog_counts, top_n, true)
+        .into_iter()
+        .map(|(author, _)| {
+            let mean = reblogged_authors[&author].stats.mean();
+            (author, mean)
+        })
+        .collect();
+    dump_group_ranking(
+        "Most Reblogged Authors",
+        "most_reblogged_authors.txt",
+        "@",
+        &most_reblogged,
+        reblogged_authors,
+        output_dir,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{aggregate, exported_status};
+    use crate::ProcessingOptions;
+    use serde_json::{from_str, json, Value};
+    use std::collections::HashSet;
+    
+    #[test]
+    fn filters_apply_to_boosts_counted_separately() {
+        let status = |fields: Value| from_str::<Value>(&exported_status(fields)).unwrap()["doc"].clone();
+        let boost = |original: Value| exported_status(json!({"language": null, "sentiment": null, "reblog": original}));
+        let bot = json!({"id": "1", "username": "newsbot", "acct": "newsbot@example.org", "bot": true});
+        let lines = [
+            boost(status(json!({"language": "en", "sentiment": 0.5}))),
+            boost(status(json!({"language": "de", "sentiment": -0.5}))),
+            boost(status(json!({"language": "en", "sentiment": -0.25, "account": bot}))),
+            exported_status(json!({"language": "de"})),
+        ];
+        let options = ProcessingOptions {
+            reblog_mode: ReblogMode::Separate,
+            languages: Some(HashSet::from(["en".to_string()])),
+            exclude_bots: true,
+            ..ProcessingOptions::default()
+        };
+        let (aggregates, _) = aggregate(&lines, &options);
+        assert_eq!(aggregates.reblog_stats.count, 1);
+        assert_eq!(aggregates.reblog_stats.sum, 0.5);
+        assert_eq!(aggregates.category_filtered, 1);
+        assert!(aggregates.language_stats.is_empty());
+        
+        // Counted as their originals, boosts pass the same filters
+        let options = ProcessingOptions { reblog_mode: ReblogMode::Original, ..options };
+        let (aggregates, _) = aggregate(&lines, &options);
+        assert_eq!(aggregates.language_stats["en"].count, 1);
+        assert_eq!(aggregates.category_filtered, 1);
+    }
+}
@@ -0,0 +1,747 @@
+//! Mergeable sketches: distinct counts, sentiment distributions and approximate user rankings
+
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::{csv_field, print_ranking, stable_hash, write_csv, write_ranking, SENTIMENT_BINS};
+
+// HyperLogLog precision: 2^12 registers, a relative standard error of 1.04 / sqrt(4096) ≈ 1.6%
+const HLL_PRECISION: u32 = 12;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+// Registers are kept sparsely until this many are set, which keeps rarely used keys small
+const HLL_SPARSE_LIMIT: usize = HLL_REGISTERS / 16;
+
+// Mixes the bits of a 64-bit hash (the MurmurHash3 finaliser); FNV-1a alone is too weak for HyperLogLog
+fn mix_hash(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+// Mergeable approximate distinct counter
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub(crate) struct HyperLogLog {
+    // (register, value) pairs while few registers are set
+    sparse: HashMap<u16, u8>,
+    // All registers once the sparse form has grown past HLL_SPARSE_LIMIT
+    dense: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub(crate) fn insert(&mut self, item: &str) {
+        let hash = mix_hash(stable_hash(item));
+        let register = (hash >> (64 - HLL_PRECISION)) as u16;
+        let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.set(register, rank);
+    }
+    
+    fn set(&mut self, register: u16, rank: u8) {
+        if self.dense.is_empty() {
+            let value = self.sparse.entry(register).or_insert(0);
+            *value = (*value).max(rank);
+            if self.sparse.len() > HLL_SPARSE_LIMIT {
+                self.dense = vec![0; HLL_REGISTERS];
+                for (register, rank) in self.sparse.drain() {
+                    self.dense[register as usize] = rank;
+                }
+            }
+        } else {
+            let value = &mut self.dense[register as usize];
+            *value = (*value).max(rank);
+        }
+    }
+    
+    pub(crate) fn merge(&mut self, other: &HyperLogLog) {
+        if other.dense.is_empty() {
+            for (&register, &rank) in &other.sparse {
+                self.set(register, rank);
+            }
+        } else {
+            for (register, &rank) in other.dense.iter().enumerate() {
+                if rank > 0 {
+                    self.set(register as u16, rank);
+                }
+            }
+        }
+    }
+    
+    fn estimate(&self) -> f64 {
+        let m = HLL_REGISTERS as f64;
+        let (sum, zeros) = if self.dense.is_empty() {
+            let sum: f64 = self.sparse.values().map(|&rank| 2f64.powi(-(rank as i32))).sum();
+            let zeros = HLL_REGISTERS - self.sparse.len();
+            (sum + zeros as f64, zeros)
+        } else {
+            let sum = self.dense.iter().map(|&rank| 2f64.powi(-(rank as i32))).sum();
+            (sum, self.dense.iter().filter(|&&rank| rank == 0).count())
+        };
+        
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let raw = alpha * m * m / sum;
+        // Linear counting is more accurate while many registers are still empty
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+    
+    pub(crate) fn count(&self) -> usize {
+        self.estimate().round() as usize
+    }
+    
+    // One standard error of the estimate
+    pub(crate) fn standard_error(&self) -> f64 {
+        self.estimate() * 1.04 / (HLL_REGISTERS as f64).sqrt()
+    }
+}
+
+pub(crate) fn dump_distinct_users(
+    all_users: &HyperLogLog,
+    hour_users: &HashMap<String, HyperLogLog>,
+    day_users: &HashMap<String, HyperLogLog>,
+    output_dir: &Path,
+) {
+    let summary = format!(
+        "About {} distinct users (± {:.1}, one standard error; hourly and daily counts in distinct_users.csv)",
+        all_users.count(),
+        all_users.standard_error()
+    );
+    print_ranking("Distinct Users", &[summary]);
+    
+    let mut rows = vec![format!("all,,{},{:.2}", all_users.count(), all_users.standard_error())];
+    for (bucket, sketches, suffix) in [("day", day_users, ""), ("hour", hour_users, ":00")] {
+        let mut keys: Vec<_> = sketches.keys().collect();
+        keys.sort();
+        for key in keys {
+            let sketch = &sketches[key];
+            rows.push(format!("{},{}{},{},{:.2}", bucket, key, suffix, sketch.count(), sketch.standard_error()));
+        }
+    }
+    write_csv("distinct_users.csv", "bucket,key,distinct_users,standard_error", rows, output_dir);
+}
+
+// -----------------------------------
+// Sentiment distribution sketches
+// -----------------------------------
+// t-digest compression: the digest keeps on the order of this many centroids, with the
+// smallest ones at the tails so extreme quantiles stay accurate
+const DIGEST_COMPRESSION: f64 = 100.0;
+// Values buffered before they are folded into the centroids. Digests with few centroids (one
+// per user, say) fold after fewer values, so a key that sees little data holds little memory.
+const DIGEST_BUFFER: usize = 500;
+const DIGEST_MIN_BUFFER: usize = 16;
+// Quantiles reported for every distribution
+const REPORTED_QUANTILES: [(&str, f64); 5] = [("p10", 0.1), ("p25", 0.25), ("median", 0.5), ("p75", 0.75), ("p90", 0.9)];
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+// Mergeable sketch of a distribution (merging t-digest with the arcsine scale function)
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+pub(crate) struct TDigest {
+    centroids: Vec<Centroid>,
+    buffer: Vec<f64>,
+    min: f64,
+    max: f64,
+}
+
+// Scale function k1: centroids may span at most one unit of k
+fn digest_scale(q: f64) -> f64 {
+    DIGEST_COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).clamp(-1.0, 1.0).asin()
+}
+
+impl TDigest {
+    pub(crate) fn add(&mut self, value: f64) {
+        if self.is_empty() {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.buffer.push(value);
+        if self.buffer.len() >= (4 * self.centroids.len()).clamp(DIGEST_MIN_BUFFER, DIGEST_BUFFER) {
+            self.compress();
+        }
+    }
+    
+    pub(crate) fn merge(&mut self, other: &TDigest) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        self.centroids.extend_from_slice(&other.centroids);
+        self.buffer.extend_from_slice(&other.buffer);
+        self.compress();
+    }
+    
+    fn is_empty(&self) -> bool {
+        self.centroids.is_empty() && self.buffer.is_empty()
+    }
+    
+    pub(crate) fn count(&self) -> usize {
+        (self.centroids.iter().map(|centroid| centroid.weight).sum::<f64>() + self.buffer.len() as f64).round() as usize
+    }
+    
+    // Fold the buffer into the centroids, merging neighbours while the scale function allows
+    fn compress(&mut self) {
+        let mut items: Vec<Centroid> = self.centroids.drain(..)
+            .chain(self.buffer.drain(..).map(|value| Centroid { mean: value, weight: 1.0 }))
+            .collect();
+        if items.is_empty() {
+            return;
+        }
+        items.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+        
+        let total: f64 = items.iter().map(|centroid| centroid.weight).sum();
+        let mut weight_before = 0.0;
+        let mut k_limit = digest_scale(0.0) + 1.0;
+        let mut current = items[0];
+        for item in &items[1..] {
+            let q = (weight_before + current.weight + item.weight) / total;
+            if digest_scale(q) <= k_limit {
+                current.weight += item.weight;
+                current.mean += (item.mean - current.mean) * item.weight / current.weight;
+            } else {
+                weight_before += current.weight;
+                k_limit = digest_scale(weight_before / total) + 1.0;
+                self.centroids.push(current);
+                current = *item;
+            }
+        }
+        self.centroids.push(current);
+    }
+    
+    // Centroids including any buffered values
+    fn compressed(&self) -> Vec<Centroid> {
+        if self.buffer.is_empty() {
+            self.centroids.clone()
+        } else {
+            let mut digest = self.clone();
+            digest.compress();
+            digest.centroids
+        }
+    }
+    
+    // Value below which a fraction `q` of the distribution lies, interpolating between centroid centres
+    fn quantile(&self, q: f64) -> Option<f64> {
+        let centroids = self.compressed();
+        let total: f64 = centroids.iter().map(|centroid| centroid.weight).sum();
+        if total == 0.0 {
+            return None;
+        }
+        let target = q.clamp(0.0, 1.0) * total;
+        
+        // (cumulative weight, value) points: the minimum, every centroid centre, the maximum
+        let mut previous = (0.0, self.min);
+        let mut weight_before = 0.0;
+        for centroid in &centroids {
+            let point = (weight_before + centroid.weight / 2.0, centroid.mean);
+            if target <= point.0 {
+                return Some(interpolate(previous, point, target));
+            }
+            previous = point;
+            weight_before += centroid.weight;
+        }
+        Some(interpolate(previous, (total, self.max), target))
+    }
+    
+    // Fraction of the distribution at or below `value`
+    fn cdf(&self, value: f64) -> f64 {
+        let centroids = self.compressed();
+        let total: f64 = centroids.iter().map(|centroid| centroid.weight).sum();
+        if total == 0.0 || value < self.min {
+            return 0.0;
+        }
+        if value >= self.max {
+            return 1.0;
+        }
+        
+        let mut previous = (self.min, 0.0);
+        let mut weight_before = 0.0;
+        for centroid in &centroids {
+            let point = (centroid.mean, weight_before + centroid.weight / 2.0);
+            if value < point.0 {
+                return interpolate(previous, point, value) / total;
+            }
+            previous = point;
+            weight_before += centroid.weight;
+        }
+        interpolate(previous, (self.max, total), value) / total
+    }
+    
+    // Estimated number of values in each of the SENTIMENT_BINS equal-width bins over [-1, 1]
+    fn histogram(&self) -> [f64; SENTIMENT_BINS] {
+        let total = self.count() as f64;
+        let width = 2.0 / SENTIMENT_BINS as f64;
+        let mut bins = [0.0; SENTIMENT_BINS];
+        let mut below = 0.0;
+        for (i, bin) in bins.iter_mut().enumerate() {
+            // Values outside [-1, 1] fall into the outermost bins
+            let upper = if i + 1 == SENTIMENT_BINS { 1.0 } else { self.cdf(-1.0 + (i + 1) as f64 * width) };
+            *bin = (upper - below) * total;
+            below = upper;
+        }
+        bins
+    }
+}
+
+// Linear interpolation of `x` between the points `from` and `to`
+fn interpolate(from: (f64, f64), to: (f64, f64), x: f64) -> f64 {
+    if to.0 <= from.0 {
+        to.1
+    } else {
+        from.1 + (to.1 - from.1) * (x - from.0) / (to.0 - from.0)
+    }
+}
+
+fn format_quantiles(digest: &TDigest) -> String {
+    REPORTED_QUANTILES.iter()
+        .map(|&(_, q)| digest.quantile(q).map(|value| value.to_string()).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub(crate) fn dump_sentiment_distribution(
+    all_digest: &TDigest,
+    hour_digests: &HashMap<String, TDigest>,
+    user_digests: &HashMap<String, TDigest>,
+    user_sentiment: &HashMap<String, (String, f64)>,
+    min_posts: usize,
+    output_dir: &Path,
+) {
+    let summary: Vec<String> = REPORTED_QUANTILES.iter()
+        .filter_map(|&(name, q)| all_digest.quantile(q).map(|value| format!("{}: {:+.4}", name, value)))
+        .collect();
+    print_ranking("Sentiment Distribution", &summary);
+    
+    let quantile_header = REPORTED_QUANTILES.iter().map(|&(name, _)| name).collect::<Vec<_>>().join(",");
+    let mut hours: Vec<_> = hour_digests.keys().collect();
+    hours.sort();
+    
+    let rows = hours.iter().map(|&hour| {
+        let digest = &hour_digests[hour];
+        format!("{}:00,{},{}", hour, digest.count(), format_quantiles(digest))
+    });
+    write_csv("hour_quantiles.csv", &format!("hour,posts,{}", quantile_header), rows, output_dir);
+    
+    let mut users: Vec<_> = user_digests.iter().filter(|(_, digest)| digest.count() >= min_posts).collect();
+    users.sort_by_key(|(user_id, digest)| (Reverse(digest.count()), *user_id));
+    let rows = users.into_iter().map(|(user_id, digest)| {
+        let username = user_sentiment.get(user_id).map(|(username, _)| username.as_str()).unwrap_or_default();
+        format!("{},{},{},{}", csv_field(user_id), csv_field(username), digest.count(), format_quantiles(digest))
+    });
+    write_csv("user_quantiles.csv", &format!("user_id,username,posts,{}", quantile_header), rows, output_dir);
+    
+    // Histograms of all statuses and of every hour over the engagement breakdown's bins
+    let width = 2.0 / SENTIMENT_BINS as f64;
+    let bin_header = (0..SENTIMENT_BINS)
+        .map(|i| format!("{:+.1}..{:+.1}", -1.0 + i as f64 * width, -1.0 + (i + 1) as f64 * width))
+        .collect::<Vec<_>>()
+        .join(",");
+    let buckets = std::iter::once(("all".to_string(), all_digest))
+        .chain(hours.into_iter().map(|hour| (format!("{}:00", hour), &hour_digests[hour])));
+    let rows = buckets.map(|(bucket, digest)| {
+        let bins = digest.histogram().iter().map(|count| format!("{:.1}", count)).collect::<Vec<_>>().join(",");
+        format!("{},{},{}", bucket, digest.count(), bins)
+    });
+    write_csv("sentiment_histogram.csv", &format!("bucket,posts,{}", bin_header), rows, output_dir);
+}
+
+// -----------------------------------
+// Approximate user rankings (--approx-users)
+// -----------------------------------
+// Sketch sizes for --approx-users
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ApproxUserParams {
+    // Space-Saving counters per ranking
+    pub(crate) capacity: usize,
+    // Count-Min error as a fraction of the total sentiment mass
+    pub(crate) epsilon: f64,
+    // Probability that a Count-Min estimate exceeds its error bound
+    pub(crate) delta: f64,
+}
+
+// Count-Min sketch of non-negative per-key sums: estimates never undercount and, with
+// probability 1 - delta, overcount by at most epsilon times the total
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counts: Vec<f64>,
+    total: f64,
+}
+
+impl CountMinSketch {
+    pub(crate) fn new(epsilon: f64, delta: f64) -> Self {
+        let width = (std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1.0 / delta).ln().ceil().max(1.0) as usize;
+        CountMinSketch { width, depth, counts: vec![0.0; width * depth], total: 0.0 }
+    }
+    
+    // Cell of `key` in every row; each row uses an independently seeded hash
+    fn cells<'a>(&self, key: &'a str) -> impl Iterator<Item = usize> + 'a {
+        let hash = stable_hash(key);
+        let width = self.width;
+        (0..self.depth).map(move |row| {
+            let seeded = mix_hash(hash ^ (row as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15));
+            row * width + (seeded % width as u64) as usize
+        })
+    }
+    
+    pub(crate) fn add(&mut self, key: &str, value: f64) {
+        for cell in self.cells(key).collect::<Vec<_>>() {
+            self.counts[cell] += value;
+        }
+        self.total += value;
+    }
+    
+    fn estimate(&self, key: &str) -> f64 {
+        self.cells(key).map(|cell| self.counts[cell]).fold(f64::INFINITY, f64::min)
+    }
+    
+    pub(crate) fn merge(&mut self, other: &CountMinSketch) {
+        assert_eq!((self.width, self.depth), (other.width, other.depth), "Count-Min sketches differ in size");
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other_count;
+        }
+        self.total += other.total;
+    }
+    
+    // Maximum overcount of an estimate, holding with probability 1 - delta
+    fn error_bound(&self) -> f64 {
+        std::f64::consts::E / self.width as f64 * self.total
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct SpaceSavingEntry {
+    username: String,
+    count: f64,
+    // Upper bound on how much of `count` was inherited from evicted keys
+    error: f64,
+}
+
+// Space-Saving summary of the keys with the largest non-negative sums. Every key whose
+// true sum exceeds total / capacity is guaranteed to be monitored.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct SpaceSaving {
+    capacity: usize,
+    entries: HashMap<String, SpaceSavingEntry>,
+    total: f64,
+    // (count bits, key) of every entry, for finding the minimum; rebuilt after deserialisation.
+    // The bit patterns of non-negative floats order like the floats themselves.
+    #[serde(skip)]
+    by_count: BTreeSet<(u64, String)>,
+}
+
+impl SpaceSaving {
+    pub(crate) fn new(capacity: usize) -> Self {
+        SpaceSaving { capacity, entries: HashMap::new(), total: 0.0, by_count: BTreeSet::new() }
+    }
+    
+    pub(crate) fn add(&mut self, key: &str, username: &str, value: f64) {
+        if self.by_count.len() != self.entries.len() {
+            self.by_count = self.entries.iter().map(|(key, entry)| (entry.count.to_bits(), key.clone())).collect();
+        }
+        self.total += value;
+        
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.by_count.remove(&(entry.count.to_bits(), key.to_string()));
+            entry.count += value;
+            self.by_count.insert((entry.count.to_bits(), key.to_string()));
+            return;
+        }
+        
+        // A new key replaces the smallest counter once the summary is full and inherits its count as error
+        let mut error = 0.0;
+        if self.entries.len() >= self.capacity {
+            let (_, evicted) = self.by_count.pop_first().expect("Space-Saving summary has no counters");
+            error = self.entries.remove(&evicted).map(|entry| entry.count).unwrap_or_default();
+        }
+        let entry = SpaceSavingEntry { username: username.to_string(), count: error + value, error };
+        self.by_count.insert((entry.count.to_bits(), key.to_string()));
+        self.entries.insert(key.to_string(), entry);
+    }
+    
+    // Count every key not in the summary may have had
+    fn min_count(&self) -> f64 {
+        if self.entries.len() < self.capacity {
+            0.0
+        } else {
+            self.entries.values().map(|entry| entry.count).fold(f64::INFINITY, f64::min)
+        }
+    }
+    
+    // Combine two summaries; a key missing from one side is charged that side's minimum
+    // count, which keeps both the counts and the errors upper bounds (Agarwal et al., 2012)
+    pub(crate) fn merge(&mut self, other: &SpaceSaving) {
+        let (self_min, other_min) = (self.min_count(), other.min_count());
+        let mut combined: HashMap<String, SpaceSavingEntry> = HashMap::new();
+        for key in self.entries.keys().chain(other.entries.keys()) {
+            if combined.contains_key(key) {
+                continue;
+            }
+            let mine = self.entries.get(key);
+            let theirs = other.entries.get(key);
+            let side = |entry: Option<&SpaceSavingEntry>, min: f64| entry.map_or((min, min), |entry| (entry.count, entry.error));
+            let (count, error) = (side(mine, self_min), side(theirs, other_min));
+            let username = mine.or(theirs).map(|entry| entry.username.clone()).unwrap_or_default();
+            combined.insert(key.clone(), SpaceSavingEntry { username, count: count.0 + count.1, error: error.0 + error.1 });
+        }
+        
+        let mut entries: Vec<_> = combined.into_iter().collect();
+        entries.sort_by(|a, b| b.1.count.total_cmp(&a.1.count).then_with(|| a.0.cmp(&b.0)));
+        entries.truncate(self.capacity);
+        self.entries = entries.into_iter().collect();
+        self.total += other.total;
+        self.by_count.clear();
+    }
+}
+
+// Bounded-memory replacement for the exact per-user sentiment sums. Positive and negative
+// sentiment are sketched separately so every sketch only sees non-negative values.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub(crate) struct ApproxUsers {
+    positive: CountMinSketch,
+    negative: CountMinSketch,
+    // Candidates for the happiest users, ranked by positive sentiment
+    happy: SpaceSaving,
+    // Candidates for the saddest users, ranked by negative sentiment
+    sad: SpaceSaving,
+    delta: f64,
+}
+
+// An estimated user total with the interval that holds with probability 1 - delta
+pub(crate) struct ApproxUserTotal {
+    pub(crate) user_id: String,
+    pub(crate) username: String,
+    pub(crate) estimate: f64,
+    lower: f64,
+    upper: f64,
+}
+
+impl ApproxUsers {
+    pub(crate) fn new(params: ApproxUserParams) -> Self {
+        ApproxUsers {
+            positive: CountMinSketch::new(params.epsilon, params.delta),
+            negative: CountMinSketch::new(params.epsilon, params.delta),
+            happy: SpaceSaving::new(params.capacity),
+            sad: SpaceSaving::new(params.capacity),
+            delta: params.delta,
+        }
+    }
+    
+    pub(crate) fn add(&mut self, user_id: &str, username: &str, sentiment: f64) {
+        if sentiment > 0.0 {
+            self.positive.add(user_id, sentiment);
+            self.happy.add(user_id, username, sentiment);
+        } else if sentiment < 0.0 {
+            self.negative.add(user_id, -sentiment);
+            self.sad.add(user_id, username, -sentiment);
+        }
+    }
+    
+    pub(crate) fn merge(&mut self, other: &ApproxUsers) {
+        self.positive.merge(&other.positive);
+        self.negative.merge(&other.negative);
+        self.happy.merge(&other.happy);
+        self.sad.merge(&other.sad);
+    }
+    
+    // Both Count-Min estimates overcount, so the net total can be too high by the positive
+    // error or too low by the negative error
+    fn total(&self, user_id: &str, username: &str) -> ApproxUserTotal {
+        let estimate = self.positive.estimate(user_id) - self.negative.estimate(user_id);
+        ApproxUserTotal {
+            user_id: user_id.to_string(),
+            username: username.to_string(),
+            estimate,
+            lower: estimate - self.positive.error_bound(),
+            upper: estimate + self.negative.error_bound(),
+        }
+    }
+    
+    // Top `n` users by estimated total sentiment among the Space-Saving candidates
+    pub(crate) fn ranking(&self, n: usize, largest: bool) -> Vec<ApproxUserTotal> {
+        let candidates = if largest { &self.happy } else { &self.sad };
+        let mut totals: Vec<_> = candidates.entries.iter()
+            .map(|(user_id, entry)| self.total(user_id, &entry.username))
+            .collect();
+        totals.sort_by(|a, b| {
+            let order = if largest { b.estimate.total_cmp(&a.estimate) } else { a.estimate.total_cmp(&b.estimate) };
+            order.then_with(|| a.user_id.cmp(&b.user_id))
+        });
+        totals.truncate(n);
+        totals
+    }
+}
+
+pub(crate) fn dump_approx_users(approx: &ApproxUsers, happiest: &[ApproxUserTotal], saddest: &[ApproxUserTotal], output_dir: &Path) {
+    let confidence = 100.0 * (1.0 - approx.delta);
+    let lines = [
+        format!(
+            "Totals are Count-Min estimates: within -{:.4} / +{:.4} of the true total with {}% probability",
+            approx.positive.error_bound(), approx.negative.error_bound(), confidence
+        ),
+        format!(
+            "Happiest candidates: {} counters; every user with more than {:.4} positive sentiment is included",
+            approx.happy.capacity, approx.happy.total / approx.happy.capacity as f64
+        ),
+        format!(
+            "Saddest candidates: {} counters; every user with more than {:.4} negative sentiment is included",
+            approx.sad.capacity, approx.sad.total / approx.sad.capacity as f64
+        ),
+    ];
+    
+    let mut report = lines.to_vec();
+    for (title, users) in [("Happiest", happiest), ("Saddest", saddest)] {
+        report.push(String::new());
+        report.push(format!("{} users (estimate [lower, upper]):", title));
+        report.extend(users.iter().enumerate().map(|(i, user)| {
+            format!(
+                "{}. {} (ID: {}) with total sentiment {:+.4} [{:+.4}, {:+.4}]",
+                i + 1, user.username, user.user_id, user.estimate, user.lower, user.upper
+            )
+        }));
+    }
+    print_ranking("Approximate User Rankings", &lines);
+    write_ranking("Approximate User Rankings", "approx_users.txt", &report, output_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn sketch(items: std::ops::Range<usize>) -> HyperLogLog {
+        let mut sketch = HyperLogLog::default();
+        for i in items {
+            sketch.insert(&format!("user{}", i));
+        }
+        sketch
+    }
+    
+    #[test]
+    fn hyperloglog_counts_small_sets_almost_exactly() {
+        let mut small = sketch(0..100);
+        for i in 0..100 {
+            small.insert(&format!("user{}", i));
+        }
+        // Linear counting over a sparse sketch
+        assert!(small.dense.is_empty());
+        assert!(small.count().abs_diff(100) <= 2);
+        assert_eq!(HyperLogLog::default().count(), 0);
+    }
+    
+    #[test]
+    fn hyperloglog_switches_to_dense_registers() {
+        let mut sketch = HyperLogLog::default();
+        let mut i = 0;
+        while sketch.dense.is_empty() {
+            sketch.insert(&format!("user{}", i));
+            i += 1;
+        }
+        assert!(i > HLL_SPARSE_LIMIT);
+        assert!(sketch.sparse.is_empty());
+        assert!(sketch.count().abs_diff(i) as f64 <= 3.0 * sketch.standard_error());
+        
+        let large = self::sketch(0..100_000);
+        let error = large.standard_error();
+        assert!((error / 100_000.0 - 0.016).abs() < 0.002);
+        assert!((large.count() as f64 - 100_000.0).abs() <= 3.0 * error);
+    }
+    
+    #[test]
+    fn hyperloglog_merges_like_a_union() {
+        let union = sketch(0..50_000);
+        // Dense into dense, sparse into dense and dense into sparse all equal the union sketch
+        for (left, right) in [(0..30_000, 20_000..50_000), (0..49_900, 49_900..50_000), (49_900..50_000, 0..49_900)] {
+            let mut merged = sketch(left);
+            merged.merge(&sketch(right));
+            assert_eq!(merged.count(), union.count());
+        }
+        assert!((union.count() as f64 - 50_000.0).abs() <= 3.0 * union.standard_error());
+        
+        let mut sparse = sketch(0..40);
+        sparse.merge(&sketch(20..60));
+        assert!(sparse.dense.is_empty());
+        assert!(sparse.count().abs_diff(60) <= 1);
+    }
+    
+    #[test]
+    fn small_digests_stay_small() {
+        let mut digest = TDigest::default();
+        for i in 0..10 {
+            digest.add(i as f64 / 10.0);
+        }
+        assert!(digest.buffer.capacity() <= DIGEST_MIN_BUFFER);
+        assert_eq!(digest.quantile(0.0), Some(0.0));
+        assert_eq!(digest.quantile(1.0), Some(0.9));
+        
+        // Memory grows with the centroids, not with a fixed buffer per key
+        for i in 0..100 {
+            digest.add(i as f64 / 100.0);
+        }
+        assert!(digest.buffer.capacity() <= 4 * digest.centroids.len().max(DIGEST_MIN_BUFFER));
+        assert_eq!(digest.count(), 110);
+    }
+    
+    #[test]
+    fn merged_digests_estimate_quantiles() {
+        // Two "ranks" see interleaved halves of a uniform spread over [-1, 1]
+        let mut digests = [TDigest::default(), TDigest::default()];
+        for i in 0..20_000 {
+            digests[i % 2].add(-1.0 + 2.0 * i as f64 / 19_999.0);
+        }
+        let [mut merged, other] = digests;
+        merged.merge(&other);
+        
+        assert_eq!(merged.count(), 20_000);
+        assert!(merged.centroids.len() <= DIGEST_COMPRESSION as usize);
+        for (q, expected) in [(0.01, -0.98), (0.1, -0.8), (0.5, 0.0), (0.9, 0.8), (0.99, 0.98)] {
+            let estimate = merged.quantile(q).unwrap();
+            assert!((estimate - expected).abs() < 0.01, "q{} = {}", q, estimate);
+        }
+        assert!((merged.cdf(0.5) - 0.75).abs() < 0.01);
+        let histogram = merged.histogram();
+        assert!(histogram.iter().all(|&count| (count - 2_000.0).abs() < 100.0), "{:?}", histogram);
+    }
+    
+    #[test]
+    fn approximate_users_find_heavy_hitters_across_ranks() {
+        let params = ApproxUserParams { capacity: 20, epsilon: 0.001, delta: 0.01 };
+        let mut ranks = [ApproxUsers::new(params), ApproxUsers::new(params)];
+        // Two strongly positive and one strongly negative user among many small accounts
+        for i in 0..4_000 {
+            let rank = &mut ranks[i % 2];
+            rank.add(&format!("user{}", i % 500), "", if i % 3 == 0 { -0.1 } else { 0.1 });
+            match i % 10 {
+                0 => rank.add("happy", "happy", 0.9),
+                1 => rank.add("glad", "glad", 0.5),
+                2 => rank.add("sad", "sad", -0.8),
+                _ => {}
+            }
+        }
+        let [mut merged, other] = ranks;
+        merged.merge(&other);
+        
+        let happiest: Vec<_> = merged.ranking(2, true).into_iter().map(|user| user.user_id).collect();
+        assert_eq!(happiest, ["happy", "glad"]);
+        let saddest = merged.ranking(1, false);
+        assert_eq!(saddest[0].user_id, "sad");
+        assert!(saddest[0].lower <= -320.0 && -320.0 <= saddest[0].upper);
+    }
+}
@@ -0,0 +1,326 @@
+//! External aggregation of the user and hour maps (`--memory-limit`)
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use mpi::traits::Communicator;
+use serde::{Deserialize, Serialize};
+use crate::{all_reduce_sum, exchange_between_ranks, owner_rank, Aggregates};
+
+// Rough heap footprint of one entry of the user and hour maps, including hashing overhead
+const USER_ENTRY_BYTES: usize = 128;
+const HOUR_ENTRY_BYTES: usize = 96;
+// Entries sent to their owner ranks per exchange round while reducing spilled user totals
+const SPILL_EXCHANGE_BATCH: usize = 100_000;
+
+// One line of a spill run: a key with its label (the username of a user) and partial sums
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct SpillRecord {
+    key: String,
+    label: String,
+    sum: f64,
+    count: usize,
+}
+
+impl SpillRecord {
+    pub(crate) fn merge(&mut self, other: SpillRecord) {
+        if self.label.is_empty() {
+            self.label = other.label;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+
+// Sorted run files written by one rank, with the names of the next ones
+pub(crate) struct SpillState {
+    dir: PathBuf,
+    rank: usize,
+    limit_bytes: usize,
+    user_runs: Vec<PathBuf>,
+    hour_runs: Vec<PathBuf>,
+    files_written: usize,
+}
+
+impl SpillState {
+    pub(crate) fn new(dir: PathBuf, rank: usize, limit_bytes: usize) -> Self {
+        fs::create_dir_all(&dir).expect("Failed to create spill directory");
+        SpillState { dir, rank, limit_bytes, user_runs: Vec::new(), hour_runs: Vec::new(), files_written: 0 }
+    }
+    
+    fn next_path(&mut self, kind: &str) -> PathBuf {
+        self.files_written += 1;
+        self.dir.join(format!("rank{:05}-{}-{:06}.tsv", self.rank, kind, self.files_written))
+    }
+    
+    // Estimated size of the user and hour maps of `aggregates`; other maps are not bounded
+    fn map_bytes(aggregates: &Aggregates) -> usize {
+        aggregates.user_sentiment.len() * USER_ENTRY_BYTES + aggregates.hour_sentiment.len() * HOUR_ENTRY_BYTES
+    }
+    
+    pub(crate) fn spill_if_needed(&mut self, aggregates: &mut Aggregates) {
+        if Self::map_bytes(aggregates) > self.limit_bytes {
+            self.spill(aggregates);
+        }
+    }
+    
+    // Move the user and hour maps into new sorted runs
+    fn spill(&mut self, aggregates: &mut Aggregates) {
+        if !aggregates.user_sentiment.is_empty() {
+            let path = self.next_path("users");
+            let records = aggregates.user_sentiment.drain()
+                .map(|(key, (label, sum))| SpillRecord { key, label, sum, count: 0 });
+            write_spill_run(&path, records);
+            self.user_runs.push(path);
+        }
+        if !aggregates.hour_sentiment.is_empty() {
+            let path = self.next_path("hours");
+            let mut hour_count = std::mem::take(&mut aggregates.hour_count);
+            let records = aggregates.hour_sentiment.drain().map(|(key, sum)| {
+                let count = hour_count.remove(&key).unwrap_or_default();
+                SpillRecord { key, label: String::new(), sum, count }
+            });
+            write_spill_run(&path, records);
+            self.hour_runs.push(path);
+        }
+    }
+}
+
+// Write `records` sorted by key as tab-separated lines
+fn write_spill_run<I: Iterator<Item = SpillRecord>>(path: &Path, records: I) {
+    let mut records: Vec<SpillRecord> = records.collect();
+    records.sort_by(|a, b| a.key.cmp(&b.key));
+    
+    let file = File::create(path).unwrap_or_else(|_| panic!("Failed to create spill file {}", path.display()));
+    let mut writer = BufWriter::new(file);
+    for record in records {
+        // Keys and labels are account ids, usernames and hour keys; tabs and newlines never occur
+        // in them but are replaced to keep the format intact
+        let clean = |value: &str| value.replace(['\t', '\n', '\r'], " ");
+        writeln!(writer, "{}\t{}\t{}\t{}", clean(&record.key), clean(&record.label), record.sum, record.count)
+            .expect("Failed to write spill file");
+    }
+}
+
+struct SpillRunReader {
+    lines: io::Lines<io::BufReader<File>>,
+    path: PathBuf,
+}
+
+impl Iterator for SpillRunReader {
+    type Item = SpillRecord;
+    
+    fn next(&mut self) -> Option<SpillRecord> {
+        let line = self.lines.next()?.expect("Failed to read spill file");
+        let mut fields = line.splitn(4, '\t');
+        let mut field = || fields.next().unwrap_or_else(|| panic!("Malformed line in spill file {}", self.path.display()));
+        let (key, label, sum, count) = (field().to_string(), field().to_string(), field(), field());
+        Some(SpillRecord {
+            key,
+            label,
+            sum: sum.parse().expect("Malformed sum in spill file"),
+            count: count.parse().expect("Malformed count in spill file"),
+        })
+    }
+}
+
+// K-way merge of sorted runs that yields every key once with its sums combined.
+// The run files are deleted once the merger is dropped.
+struct SpillMerger {
+    readers: Vec<SpillRunReader>,
+    heads: BinaryHeap<Reverse<(String, usize)>>,
+    pending: Vec<Option<SpillRecord>>,
+}
+
+impl SpillMerger {
+    pub(crate) fn new(paths: Vec<PathBuf>) -> Self {
+        let mut readers: Vec<SpillRunReader> = paths.into_iter()
+            .map(|path| {
+                let file = File::open(&path).unwrap_or_else(|_| panic!("Failed to open spill file {}", path.display()));
+                SpillRunReader { lines: io::BufRead::lines(io::BufReader::new(file)), path }
+            })
+            .collect();
+        let pending: Vec<Option<SpillRecord>> = readers.iter_mut().map(Iterator::next).collect();
+        let heads = pending.iter()
+            .enumerate()
+            .filter_map(|(i, record)| record.as_ref().map(|record| Reverse((record.key.clone(), i))))
+            .collect();
+        SpillMerger { readers, heads, pending }
+    }
+    
+    // Take the head record of run `i` and advance that run
+    fn advance(&mut self, i: usize) -> SpillRecord {
+        let record = self.pending[i].take().expect("Spill run has no pending record");
+        self.pending[i] = self.readers[i].next();
+        if let Some(next) = &self.pending[i] {
+            self.heads.push(Reverse((next.key.clone(), i)));
+        }
+        record
+    }
+}
+
+impl Iterator for SpillMerger {
+    type Item = SpillRecord;
+    
+    fn next(&mut self) -> Option<SpillRecord> {
+        let Reverse((_, i)) = self.heads.pop()?;
+        let mut merged = self.advance(i);
+        while let Some(Reverse((key, j))) = self.heads.peek() {
+            if *key != merged.key {
+                break;
+            }
+            let j = *j;
+            self.heads.pop();
+            let record = self.advance(j);
+            merged.merge(record);
+        }
+        Some(merged)
+    }
+}
+
+impl Drop for SpillMerger {
+    fn drop(&mut self) {
+        for reader in &self.readers {
+            let _ = fs::remove_file(&reader.path);
+        }
+    }
+}
+
+// Keep `ranking` as the `n` most extreme (highest when `largest`) user totals seen so far
+fn keep_extreme_user(ranking: &mut Vec<(String, (String, f64))>, record: &SpillRecord, n: usize, largest: bool) {
+    let better = |a: f64, b: f64| if largest { a > b } else { a < b };
+    if ranking.len() == n && !ranking.last().is_some_and(|(_, (_, last))| better(record.sum, *last)) {
+        return;
+    }
+    let index = ranking.partition_point(|(_, (_, sum))| !better(record.sum, *sum));
+    ranking.insert(index, (record.key.clone(), (record.label.clone(), record.sum)));
+    ranking.truncate(n);
+}
+
+// Finish an external aggregation after the scan. Hour runs are merged back into the hour maps.
+// User runs are merged, sent in batches to the rank owning each user, spilled again there when
+// needed, and reduced to exact totals; each rank then keeps only its `top_n` happiest and
+// saddest users in `user_sentiment`, which is enough for rank 0 to find the global top `top_n`.
+pub(crate) fn finish_external_aggregation<C: Communicator>(
+    world: &C,
+    rank: usize,
+    size: usize,
+    mut spill: SpillState,
+    aggregates: &mut Aggregates,
+    top_n: usize,
+) {
+    spill.spill(aggregates);
+    
+    for record in SpillMerger::new(std::mem::take(&mut spill.hour_runs)) {
+        aggregates.hour_count.insert(record.key.clone(), record.count);
+        aggregates.hour_sentiment.insert(record.key, record.sum);
+    }
+    
+    let mut local_users = SpillMerger::new(std::mem::take(&mut spill.user_runs));
+    let mut owned = Aggregates::default();
+    loop {
+        let batch: Vec<SpillRecord> = local_users.by_ref().take(SPILL_EXCHANGE_BATCH).collect();
+        // Every rank takes part in each exchange until all of them have sent their last batch
+        if all_reduce_sum(world, batch.len() as f64) == 0.0 {
+            break;
+        }
+        let mut outgoing: Vec<Vec<SpillRecord>> = vec![Vec::new(); size];
+        for record in batch {
+            outgoing[owner_rank(&record.key, size)].push(record);
+        }
+        for record in exchange_between_ranks(world, rank, size, outgoing).into_iter().flatten() {
+            let entry = owned.user_sentiment.entry(record.key).or_insert((record.label, 0.0));
+            entry.1 += record.sum;
+        }
+        spill.spill_if_needed(&mut owned);
+    }
+    
+    spill.spill(&mut owned);
+    let mut happiest = Vec::with_capacity(top_n + 1);
+    let mut saddest = Vec::with_capacity(top_n + 1);
+    for record in SpillMerger::new(std::mem::take(&mut spill.user_runs)) {
+        keep_extreme_user(&mut happiest, &record, top_n, true);
+        keep_extreme_user(&mut saddest, &record, top_n, false);
+    }
+    aggregates.user_sentiment = happiest.into_iter().chain(saddest).collect();
+    aggregates.spill_files += spill.files_written;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{mpi_universe, scratch_dir};
+    
+    fn record(key: &str, sum: f64, count: usize) -> SpillRecord {
+        SpillRecord { key: key.to_string(), label: format!("@{}", key), sum, count }
+    }
+    
+    #[test]
+    fn spill_runs_merge_into_one_record_per_key() {
+        let dir = scratch_dir("spill-merge");
+        let runs = vec![dir.join("a.tsv"), dir.join("b.tsv"), dir.join("c.tsv")];
+        write_spill_run(&runs[0], [record("bob", 1.0, 1), record("alice", 0.5, 2)].into_iter());
+        write_spill_run(&runs[1], [record("carol", -1.0, 1), record("bob", 0.25, 3)].into_iter());
+        write_spill_run(&runs[2], std::iter::empty());
+        
+        let merged: Vec<SpillRecord> = SpillMerger::new(runs.clone()).collect();
+        let keys: Vec<&str> = merged.iter().map(|record| record.key.as_str()).collect();
+        assert_eq!(keys, ["alice", "bob", "carol"]);
+        assert_eq!((merged[1].sum, merged[1].count), (1.25, 4));
+        assert_eq!(merged[1].label, "@bob");
+        // The runs are removed once merged
+        assert!(runs.iter().all(|run| !run.exists()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn extreme_users_keep_the_n_largest_or_smallest_totals() {
+        let mut happiest = Vec::new();
+        let mut saddest = Vec::new();
+        for (key, sum) in [("a", 0.5), ("b", -2.0), ("c", 3.0), ("d", 1.0), ("e", -0.5), ("f", 1.0)] {
+            keep_extreme_user(&mut happiest, &record(key, sum, 1), 3, true);
+            keep_extreme_user(&mut saddest, &record(key, sum, 1), 2, false);
+        }
+        let keys = |ranking: &[(String, (String, f64))]| ranking.iter().map(|(key, _)| key.clone()).collect::<Vec<_>>();
+        // Ties keep the user seen first
+        assert_eq!(keys(&happiest), ["c", "d", "f"]);
+        assert_eq!(keys(&saddest), ["b", "e"]);
+        assert_eq!(happiest[0].1, ("@c".to_string(), 3.0));
+    }
+    
+    #[test]
+    fn external_aggregation_matches_in_memory_totals() {
+        let dir = scratch_dir("spill-finish");
+        // A zero limit spills after every status
+        let mut spill = SpillState::new(dir.clone(), 0, 0);
+        let mut aggregates = Aggregates::default();
+        let statuses = [("alice", 0.5), ("bob", -1.0), ("alice", 0.25), ("carol", 2.0), ("bob", -0.5), ("dave", 0.0)];
+        for (i, (user, sentiment)) in statuses.iter().enumerate() {
+            let entry = aggregates.user_sentiment.entry(user.to_string()).or_insert((format!("@{}", user), 0.0));
+            entry.1 += sentiment;
+            let hour = format!("2025-02-01 0{}", i % 2);
+            *aggregates.hour_sentiment.entry(hour.clone()).or_default() += sentiment;
+            *aggregates.hour_count.entry(hour).or_default() += 1;
+            spill.spill_if_needed(&mut aggregates);
+        }
+        assert!(aggregates.user_sentiment.is_empty());
+        
+        let universe = mpi_universe();
+        let world = universe.world();
+        finish_external_aggregation(&world, 0, 1, spill, &mut aggregates, 1);
+        drop(universe);
+        
+        assert_eq!(aggregates.hour_count["2025-02-01 00"], 3);
+        assert_eq!(aggregates.hour_count["2025-02-01 01"], 3);
+        assert_eq!(aggregates.hour_sentiment["2025-02-01 01"], 1.0);
+        // Only the happiest and saddest user are kept, with totals over every run
+        assert_eq!(aggregates.user_sentiment.len(), 2);
+        assert_eq!(aggregates.user_sentiment["carol"], ("@carol".to_string(), 2.0));
+        assert_eq!(aggregates.user_sentiment["bob"], ("@bob".to_string(), -1.5));
+        assert!(aggregates.spill_files >= statuses.len());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//! Text extraction from status HTML and the extract-text export
+
+use std::io::Write;
+use std::path::Path;
+use serde::Serialize;
+use crate::{create_output_file, for_each_chunk_line, url_host, MastodonData};
+
+// Plain text of a status, with the link domains and custom emoji pulled out of it
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub(crate) struct ExtractedText {
+    pub(crate) text: String,
+    link_domains: Vec<String>,
+    emojis: Vec<String>,
+}
+
+// Tags that separate words when the markup is removed
+const BLOCK_TAGS: &[&str] = &["p", "br", "div", "li", "ul", "ol", "blockquote", "pre", "h1", "h2", "h3", "h4", "h5", "h6"];
+
+// Value of the `name` attribute inside the body of an opening tag
+fn tag_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(index) = rest.find(name) {
+        let at_boundary = rest[..index].ends_with(char::is_whitespace);
+        let after = &rest[index + name.len()..];
+        rest = after;
+        let value = match after.trim_start().strip_prefix('=') {
+            Some(value) if at_boundary => value.trim_start(),
+            _ => continue,
+        };
+        return match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next(),
+            _ => value.split(char::is_whitespace).next(),
+        };
+    }
+    None
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let value = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(value)
+        }
+    }
+}
+
+// Named and numeric character references; anything unrecognised is kept verbatim
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let entity = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[..end])?, end + 1)));
+        match entity {
+            Some((c, consumed)) => {
+                decoded.push(c);
+                rest = &rest[consumed..];
+            }
+            None => decoded.push('&'),
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// Move `:shortcode:` custom emoji out of the text into `emojis`. When the status lists its
+// custom emoji only those shortcodes are taken; otherwise any `:word:` with a letter in it is.
+fn extract_emojis(text: &str, shortcodes: Option<&[String]>, emojis: &mut Vec<String>) -> String {
+    let mut kept = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        let candidate = &rest[start + 1..];
+        let len = candidate
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(candidate.len());
+        let shortcode = &candidate[..len];
+        let is_emoji = match shortcodes {
+            Some(shortcodes) => shortcodes.iter().any(|known| known == shortcode),
+            None => {
+                let at_boundary = !rest[..start].ends_with(char::is_alphanumeric);
+                at_boundary && len >= 2 && shortcode.chars().any(|c| c.is_ascii_alphabetic())
+            }
+        };
+        if is_emoji && candidate[len..].starts_with(':') {
+            kept.push_str(&rest[..start]);
+            kept.push(' ');
+            emojis.push(shortcode.to_string());
+            rest = &candidate[len + 1..];
+        } else {
+            kept.push_str(&rest[..=start]);
+            rest = candidate;
+        }
+    }
+    kept.push_str(rest);
+    kept
+}
+
+// Turn Mastodon status HTML into clean text: tags are stripped, entities decoded, the
+// `invisible` spans Mastodon uses to shorten links dropped, and whitespace collapsed.
+// Links other than mentions and hashtags contribute their domain to `link_domains`.
+// `shortcodes` are the custom emoji from the status's `emojis` array, when it has one.
+pub(crate) fn extract_text(html: &str, shortcodes: Option<&[String]>) -> ExtractedText {
+    let mut extracted = ExtractedText::default();
+    let mut raw = String::with_capacity(html.len());
+    // Number of open spans inside an invisible span; 0 while text is visible
+    let mut hidden_depth = 0usize;
+    
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        if hidden_depth == 0 {
+            raw.push_str(&rest[..start]);
+        }
+        let Some(end) = rest[start..].find('>') else {
+            // Unterminated tag; nothing after it is text
+            rest = "";
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+        
+        let (closing, body) = match tag.strip_prefix('/') {
+            Some(body) => (true, body),
+            None => (false, tag),
+        };
+        let name = body
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let class = tag_attribute(body, "class").unwrap_or_default();
+        
+        match name.as_str() {
+            "span" if hidden_depth > 0 => {
+                if closing {
+                    hidden_depth -= 1;
+                } else {
+                    hidden_depth += 1;
+                }
+            }
+            "span" if !closing && class.split_whitespace().any(|c| c == "invisible") => hidden_depth = 1,
+            "a" if !closing && !class.split_whitespace().any(|c| c == "mention") => {
+                if let Some(domain) = tag_attribute(body, "href").map(decode_entities).as_deref().and_then(url_host) {
+                    let domain = domain.to_lowercase();
+                    if !extracted.link_domains.contains(&domain) {
+                        extracted.link_domains.push(domain);
+                    }
+                }
+            }
+            _ if BLOCK_TAGS.contains(&name.as_str()) => raw.push(' '),
+            _ => {}
+        }
+    }
+    if hidden_depth == 0 {
+        raw.push_str(rest);
+    }
+    
+    let text = extract_emojis(&decode_entities(&raw), shortcodes, &mut extracted.emojis);
+    extracted.text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    extracted
+}
+
+// One line of `extract-text` output
+#[derive(Debug, Serialize)]
+struct ExtractedRecord<'a> {
+    status_id: Option<&'a str>,
+    created_at: Option<&'a str>,
+    language: Option<&'a str>,
+    #[serde(flatten)]
+    extracted: ExtractedText,
+}
+
+// Every rank writes the extracted text of the statuses in its byte range as NDJSON,
+// returning the number of records written
+pub(crate) fn dump_extracted_text(
+    input_file: &str,
+    local_start: u64,
+    local_end: u64,
+    max_buffer_size: usize,
+    rank: usize,
+    output_dir: &Path,
+) -> usize {
+    let file_name = format!("extracted_text.part-{:05}.ndjson", rank);
+    let mut writer = create_output_file(output_dir, &file_name);
+    
+    let mut records = 0;
+    for_each_chunk_line(input_file, local_start, local_end, max_buffer_size, |_, line| {
+        let Ok(mastodon_data) = MastodonData::from_json_str(line) else {
+            return;
+        };
+        let Some(content) = mastodon_data.content.as_deref() else {
+            return;
+        };
+        let record = ExtractedRecord {
+            status_id: mastodon_data.status_id.as_deref(),
+            created_at: mastodon_data.created_at.as_deref(),
+            language: mastodon_data.language.as_deref(),
+            extracted: extract_text(content, mastodon_data.emojis.as_deref()),
+        };
+        serde_json::to_writer(&mut writer, &record).expect("Failed to write to file");
+        writeln!(writer).expect("Failed to write to file");
+        records += 1;
+    });
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn extract_text_strips_markup_and_collects_link_domains() {
+        let html = concat!(
+            r#"<p>Caf&eacute; &amp; tea&#33;<br>Read <a href="https://www.example.org/a?b=1&amp;c=2" rel="nofollow">"#,
+            r#"<span class="invisible">https://www.</span><span class="">example.org/a</span>"#,
+            r#"<span class="invisible">?b=1&amp;c=2</span></a></p>"#,
+            r#"<p><a href="https://mastodon.social/@bob" class="u-url mention">@<span>bob</span></a> "#,
+            r#"<a href="https://mastodon.social/tags/rust" class="mention hashtag">#<span>rust</span></a></p>"#,
+        );
+        let extracted = extract_text(html, Some(&[]));
+        assert_eq!(extracted.text, "Caf&eacute; & tea! Read example.org/a @bob #rust");
+        assert_eq!(extracted.link_domains, ["www.example.org"]);
+        assert!(extracted.emojis.is_empty());
+    }
+    
+    #[test]
+    fn custom_emoji_come_from_the_emojis_array() {
+        let shortcodes = ["blobcat".to_string()];
+        let extracted = extract_text("<p>Meet at 10:30:45 :blobcat: :not_listed:</p>", Some(&shortcodes));
+        assert_eq!(extracted.text, "Meet at 10:30:45 :not_listed:");
+        assert_eq!(extracted.emojis, ["blobcat"]);
+        
+        // Without the array, shortcodes are scraped from the text
+        let extracted = extract_text("<p>Meet at 10:30:45 :blobcat: a:b:c</p>", None);
+        assert_eq!(extracted.text, "Meet at 10:30:45 a:b:c");
+        assert_eq!(extracted.emojis, ["blobcat"]);
+    }
+    
+    #[test]
+    fn unterminated_tags_and_unknown_entities_are_kept_safe() {
+        assert_eq!(extract_text("<p>one &bogus; two</p><a href=", None).text, "one &bogus; two");
+        assert_eq!(extract_text("x &#x1F600; y", None).text, "x \u{1F600} y");
+    }
+}
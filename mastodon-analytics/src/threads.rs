@@ -0,0 +1,254 @@
+//! Reply threads rebuilt from statuses gathered across ranks
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use mpi::traits::*;
+use serde::{Deserialize, Serialize};
+use crate::{csv_field, exchange_between_ranks, gather_at_root, owner_rank, print_ranking, write_csv, write_ranking, ThreadNode};
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ThreadExchange {
+    nodes: Vec<ThreadNode>,
+    referenced_parents: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ThreadMetrics {
+    root_id: String,
+    root_missing: bool,
+    pub(crate) size: usize,
+    depth: usize,
+    participants: usize,
+    root_sentiment: f64,
+    pub(crate) mean_sentiment: f64,
+    leaf_sentiment: f64,
+}
+
+impl ThreadMetrics {
+    // Change in sentiment from the root to the leaves of the conversation
+    fn drift(&self) -> f64 {
+        self.leaf_sentiment - self.root_sentiment
+    }
+}
+
+// Keep only statuses that take part in a conversation and collect them on rank 0.
+// Every status is sent to the rank that owns its id together with the ids its replies point
+// at, so a parent is recognised even when its children were read by a different rank.
+pub(crate) fn collect_conversation_nodes<C: Communicator>(
+    world: &C,
+    rank: usize,
+    size: usize,
+    thread_nodes: Vec<ThreadNode>,
+) -> Vec<ThreadNode> {
+    let mut outgoing: Vec<ThreadExchange> = (0..size).map(|_| ThreadExchange::default()).collect();
+    for node in thread_nodes {
+        if let Some(parent) = &node.parent {
+            outgoing[owner_rank(parent, size)].referenced_parents.push(parent.clone());
+        }
+        outgoing[owner_rank(&node.id, size)].nodes.push(node);
+    }
+    
+    let mut owned_nodes: HashMap<String, ThreadNode> = HashMap::new();
+    let mut referenced: HashSet<String> = HashSet::new();
+    for exchange in exchange_between_ranks(world, rank, size, outgoing) {
+        referenced.extend(exchange.referenced_parents);
+        for node in exchange.nodes {
+            // Duplicate copies of a status carry the same reply link
+            owned_nodes.entry(node.id.clone()).or_insert(node);
+        }
+    }
+    
+    let conversation_nodes: Vec<ThreadNode> = owned_nodes
+        .into_values()
+        .filter(|node| node.parent.is_some() || referenced.contains(&node.id))
+        .collect();
+    
+    gather_at_root(world, rank, size, conversation_nodes).into_iter().flatten().collect()
+}
+
+// Replies whose parent is not in the dump, counted over the gathered conversation nodes
+pub(crate) fn count_unresolved_parents(nodes: &[ThreadNode]) -> usize {
+    let ids: HashSet<&str> = nodes.iter().map(|node| node.id.as_str()).collect();
+    nodes
+        .iter()
+        .filter(|node| node.parent.as_deref().is_some_and(|parent| !ids.contains(parent)))
+        .count()
+}
+
+// Assemble threads from reply links. Replies whose parent is not in the dump start a partial thread.
+pub(crate) fn build_threads(nodes: Vec<ThreadNode>) -> Vec<ThreadMetrics> {
+    let nodes: HashMap<String, ThreadNode> = nodes.into_iter().map(|node| (node.id.clone(), node)).collect();
+    
+    // Depth and root of every status, following parent links up to the first missing parent
+    let mut placement: HashMap<&str, (usize, &str)> = HashMap::new();
+    for id in nodes.keys() {
+        let mut path: Vec<&str> = Vec::new();
+        let mut current = id.as_str();
+        let (mut depth, root) = loop {
+            if let Some(&placed) = placement.get(current) {
+                break placed;
+            }
+            path.push(current);
+            let parent = nodes[current].parent
+                .as_deref()
+                .and_then(|parent| nodes.get_key_value(parent))
+                .map(|(parent, _)| parent.as_str())
+                .filter(|parent| !path.contains(parent));
+            match parent {
+                Some(parent) => current = parent,
+                None => {
+                    // Missing parent (or a reply cycle): this status roots the thread
+                    path.pop();
+                    placement.insert(current, (0, current));
+                    break (0, current);
+                }
+            }
+        };
+        while let Some(node) = path.pop() {
+            depth += 1;
+            placement.insert(node, (depth, root));
+        }
+    }
+    
+    let mut has_children: HashSet<&str> = HashSet::new();
+    for node in nodes.values() {
+        if let Some(parent) = node.parent.as_deref() {
+            if nodes.contains_key(parent) {
+                has_children.insert(parent);
+            }
+        }
+    }
+    
+    let mut threads: HashMap<&str, Vec<&ThreadNode>> = HashMap::new();
+    for (id, node) in &nodes {
+        let (_, root) = placement[id.as_str()];
+        threads.entry(root).or_default().push(node);
+    }
+    
+    threads
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(root_id, members)| {
+            let root = &nodes[root_id];
+            let participants: HashSet<&str> = members.iter().filter_map(|node| node.user_id.as_deref()).collect();
+            let leaves: Vec<f64> = members
+                .iter()
+                .filter(|node| !has_children.contains(node.id.as_str()))
+                .map(|node| node.sentiment)
+                .collect();
+            ThreadMetrics {
+                root_id: root_id.to_string(),
+                root_missing: root.parent.as_deref().is_some_and(|parent| !nodes.contains_key(parent)),
+                size: members.len(),
+                depth: members.iter().map(|node| placement[node.id.as_str()].0).max().unwrap_or(0),
+                participants: participants.len(),
+                root_sentiment: root.sentiment,
+                mean_sentiment: members.iter().map(|node| node.sentiment).sum::<f64>() / members.len() as f64,
+                leaf_sentiment: leaves.iter().sum::<f64>() / leaves.len().max(1) as f64,
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn dump_thread_ranking(title: &str, file_name: &str, threads: &[&ThreadMetrics], output_dir: &Path) {
+    let lines: Vec<String> = threads
+        .iter()
+        .enumerate()
+        .map(|(i, thread)| {
+            format!(
+                "{}. Thread {}{} with mean sentiment {:+.4} ({} posts, {} participants, depth {}, drift {:+.4})",
+                i + 1,
+                thread.root_id,
+                if thread.root_missing { " (partial, first post is a reply)" } else { "" },
+                thread.mean_sentiment,
+                thread.size,
+                thread.participants,
+                thread.depth,
+                thread.drift()
+            )
+        })
+        .collect();
+    print_ranking(title, &lines);
+    write_ranking(title, file_name, &lines, output_dir);
+}
+
+pub(crate) fn dump_thread_table(threads: &[ThreadMetrics], output_dir: &Path) {
+    let rows = threads.iter().map(|thread| {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            csv_field(&thread.root_id),
+            thread.root_missing,
+            thread.size,
+            thread.depth,
+            thread.participants,
+            thread.root_sentiment,
+            thread.mean_sentiment,
+            thread.leaf_sentiment,
+            thread.drift()
+        )
+    });
+    write_csv(
+        "threads.csv",
+        "root_id,root_missing,posts,depth,participants,root_sentiment,mean_sentiment,leaf_sentiment,drift",
+        rows,
+        output_dir,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{aggregate, exported_status, mpi_universe};
+    use crate::ProcessingOptions;
+    use serde_json::json;
+    
+    #[test]
+    fn threads_join_replies_on_one_id_space() {
+        let options = ProcessingOptions { build_threads: true, ..ProcessingOptions::default() };
+        let lines = [
+            exported_status(json!({"id": "1", "sentiment": 0.5})),
+            exported_status(json!({"id": "2", "inReplyToId": "1", "sentiment": -0.5})),
+            exported_status(json!({"id": "3", "inReplyToId": "2", "sentiment": 0.0})),
+            exported_status(json!({"id": "4", "inReplyToId": "99"})),
+            // Shaped like the sample export, which has no `id`: it is taken from the URL
+            exported_status(json!({"url": "https://mastodon.social/@alice/5/", "inReplyToId": "3", "sentiment": 0.0})),
+            // Without a URL the URI gives the id
+            exported_status(json!({"url": null, "uri": "https://mastodon.social/users/alice/statuses/6",
+                "inReplyToId": "5", "sentiment": -0.5})),
+            exported_status(json!({"url": null, "uri": null, "inReplyToId": "1"})),
+        ];
+        let (aggregates, partitioned) = aggregate(&lines, &options);
+        assert_eq!(partitioned.thread_nodes.len(), 6);
+        assert_eq!(aggregates.unlinked_replies, 1);
+        
+        let universe = mpi_universe();
+        let world = universe.world();
+        let nodes = collect_conversation_nodes(&world, 0, 1, partitioned.thread_nodes);
+        drop(universe);
+        
+        assert_eq!(count_unresolved_parents(&nodes), 1);
+        let threads = build_threads(nodes);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].root_id, "1");
+        assert!(!threads[0].root_missing);
+        assert_eq!((threads[0].size, threads[0].depth), (5, 4));
+        assert!((threads[0].drift() - -1.0).abs() < 1e-9);
+        
+        // With `inReplyToUri` in the export, replies are joined on URIs and local ids are ignored
+        let root_uri = "https://mastodon.social/users/alice/statuses/1";
+        let lines = [
+            exported_status(json!({"id": "1", "uri": root_uri, "inReplyToUri": null})),
+            exported_status(json!({
+                "id": "7",
+                "uri": "https://hachyderm.io/users/bob/statuses/7",
+                "inReplyToId": "5",
+                "inReplyToUri": root_uri,
+            })),
+        ];
+        let (_, partitioned) = aggregate(&lines, &options);
+        let threads = build_threads(partitioned.thread_nodes);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].root_id, root_uri);
+        assert_eq!(threads[0].size, 2);
+    }
+}
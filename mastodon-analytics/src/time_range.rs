@@ -0,0 +1,157 @@
+//! Restricting the scan to the statuses of a time range
+
+use std::fs::File;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use memmap2::MmapOptions;
+use serde_json::{from_str, Value};
+use crate::{next_line_start, status_time};
+
+// Accepts RFC 3339 times, `YYYY-MM-DDTHH:MM:SS` / `YYYY-MM-DD HH:MM` in UTC, or plain dates (midnight UTC)
+pub(crate) fn parse_time_bound(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").map(|time| time.and_utc()))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").map(|time| time.and_utc()))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|date| date.and_time(NaiveTime::MIN).and_utc())
+        })
+        .map_err(|_| format!("expected an RFC 3339 time or a YYYY-MM-DD date, got '{}'", value))
+}
+
+// Whether the first dated line at or after `start` is at or after `target`. Up to a few
+// undated or malformed lines are skipped; the end of the file counts as after every target.
+fn dated_line_reached(mmap: &[u8], mut start: usize, target: DateTime<Utc>) -> bool {
+    const MAX_SKIPPED_LINES: usize = 64;
+    for _ in 0..MAX_SKIPPED_LINES {
+        if start >= mmap.len() {
+            break;
+        }
+        let end = next_line_start(mmap, start + 1);
+        let time = std::str::from_utf8(&mmap[start..end])
+            .ok()
+            .and_then(|line| from_str::<Value>(line.trim()).ok())
+            .and_then(|record| {
+                let status = record.get("doc").unwrap_or(&record);
+                status.get("createdAt").or_else(|| status.get("created_at"))?.as_str().and_then(status_time)
+            });
+        if let Some(time) = time {
+            return time >= target;
+        }
+        start = end;
+    }
+    true
+}
+
+// Start of the first line dated at or after `target`, assuming the file is ordered by time
+fn locate_time_offset(mmap: &[u8], target: DateTime<Utc>) -> usize {
+    let (mut lo, mut hi) = (0, mmap.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if dated_line_reached(mmap, next_line_start(mmap, mid), target) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    next_line_start(mmap, lo)
+}
+
+// Byte range of a (mostly) time-ordered file that holds the statuses in [from, to). Both ends are
+// widened by `slack` so that slightly out-of-order statuses near the bounds are still scanned;
+// the exact bounds are applied to every record during processing.
+pub(crate) fn locate_time_range(
+    input_file: &str,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    slack: Duration,
+) -> (u64, u64) {
+    let file = File::open(input_file).expect("Failed to open input file");
+    let mmap = unsafe { MmapOptions::new().map(&file).expect("Failed to map file") };
+    
+    let start = from.map_or(0, |from| locate_time_offset(&mmap, from - slack));
+    let end = to.map_or(mmap.len(), |to| locate_time_offset(&mmap, to + slack));
+    // Chunks skip the partial line at their start, so begin on the newline before the first line
+    (start.saturating_sub(1) as u64, end.max(start) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{exported_status, scratch_dir};
+    use crate::{for_each_chunk_line, processing_data, Aggregates, PartitionedData, ProcessingOptions};
+    use chrono::TimeZone;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::fs;
+    
+    #[test]
+    fn time_bounds_narrow_the_scanned_byte_range() {
+        let start = Utc.with_ymd_and_hms(2025, 2, 1, 0, 0, 0).unwrap();
+        let lines: Vec<String> = (0..48)
+            .map(|hour| {
+                let created_at = (start + Duration::hours(hour)).format("%Y-%m-%dT%H:%M:%S.000Z").to_string();
+                exported_status(json!({"createdAt": created_at}))
+            })
+            .collect();
+        let dir = scratch_dir("time-range");
+        let path = dir.join("statuses.ndjson");
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let path = path.to_str().unwrap();
+        
+        let options = ProcessingOptions {
+            from: Some(start + Duration::hours(10)),
+            to: Some(start + Duration::hours(20)),
+            ..ProcessingOptions::default()
+        };
+        for (slack, scanned) in [(0, 11), (2, 15)] {
+            let range = locate_time_range(path, options.from, options.to, Duration::hours(slack));
+            let mut aggregates = Aggregates::default();
+            let mut partitioned = PartitionedData::default();
+            let lines_scanned = for_each_chunk_line(path, range.0, range.1, 1 << 20, |_, line| {
+                processing_data(line, &mut aggregates, &mut partitioned, &options);
+            });
+            assert_eq!(lines_scanned, scanned);
+            // Only the bounds [from, to) are counted, whatever the slack scanned
+            let mut hours: Vec<&String> = aggregates.hour_count.keys().collect();
+            hours.sort();
+            assert_eq!(hours.len(), 10);
+            assert_eq!(hours[0], "2025-02-01 10");
+            assert_eq!(hours[9], "2025-02-01 19");
+            assert_eq!(aggregates.time_filtered, scanned - 10);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn time_bound_search_steps_over_undated_lines() {
+        let start = Utc.with_ymd_and_hms(2025, 2, 1, 0, 0, 0).unwrap();
+        let dated = |hour: i64| {
+            let created_at = (start + Duration::hours(hour)).format("%Y-%m-%dT%H:%M:%S.000Z").to_string();
+            exported_status(json!({"createdAt": created_at}))
+        };
+        // A malformed head, then every third hour followed by an undated and a truncated line
+        let mut lines = vec!["[".to_string(), exported_status(json!({"createdAt": null}))];
+        let mut hour_lines = HashMap::new();
+        for hour in 0..24 {
+            hour_lines.insert(hour, lines.len());
+            lines.push(dated(hour));
+            if hour % 3 == 0 {
+                lines.push(exported_status(json!({"createdAt": "not a time"})));
+                lines.push("{\"doc\": {\"createdAt\"".to_string());
+            }
+        }
+        let text = lines.join("\n") + "\n";
+        let mut offsets = vec![0];
+        for line in &lines {
+            offsets.push(offsets.last().unwrap() + line.len() + 1);
+        }
+        let mmap = text.as_bytes();
+        
+        assert_eq!(locate_time_offset(mmap, start), 0);
+        assert_eq!(locate_time_offset(mmap, start + Duration::hours(5)), offsets[hour_lines[&5]]);
+        // Undated lines just before the first matching hour are kept in the range
+        assert_eq!(locate_time_offset(mmap, start + Duration::hours(4)), offsets[hour_lines[&3] + 1]);
+        assert_eq!(locate_time_offset(mmap, start + Duration::minutes(3 * 60 + 30)), offsets[hour_lines[&3] + 1]);
+        assert_eq!(locate_time_offset(mmap, start + Duration::hours(30)), mmap.len());
+    }
+}
@@ -0,0 +1,193 @@
+//! Hashtags trending in a window of hours against their baseline
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+use chrono::{Duration, NaiveDateTime};
+use crate::{hour_key, parse_hour_key, print_ranking, write_ranking};
+
+#[derive(Debug, Clone)]
+pub(crate) struct TrendingHashtag {
+    tag: String,
+    window_start: String,
+    window_posts: usize,
+    expected_posts: f64,
+    score: f64,
+}
+
+// Counts at sorted positions of a sparse series, answering range sums by binary search
+struct SparseCounts {
+    positions: Vec<i64>,
+    prefix: Vec<usize>,
+}
+
+impl SparseCounts {
+    fn new(mut counts: Vec<(i64, usize)>) -> Self {
+        counts.sort_unstable();
+        let mut prefix = Vec::with_capacity(counts.len() + 1);
+        prefix.push(0);
+        for (_, count) in &counts {
+            prefix.push(prefix[prefix.len() - 1] + count);
+        }
+        SparseCounts { positions: counts.into_iter().map(|(position, _)| position).collect(), prefix }
+    }
+    
+    // Sum of the counts at positions in [from, to)
+    fn sum(&self, from: i64, to: i64) -> usize {
+        let start = self.positions.partition_point(|&position| position < from);
+        let end = self.positions.partition_point(|&position| position < to);
+        self.prefix[end] - self.prefix[start]
+    }
+}
+
+// Score every `window`-hour window of each hashtag against the `baseline` hours before it.
+// The expected count scales the hashtag's baseline count by the overall volume ratio between
+// window and baseline, and the burst score is the Poisson z-score of the observed count.
+// Only the best window of each hashtag is reported. `hours` are the sorted plausible hours
+// with posts; only windows holding posts of a hashtag are scored, so gaps cost nothing.
+pub(crate) fn detect_trending_hashtags(
+    hashtag_hour_count: &HashMap<String, HashMap<String, usize>>,
+    hour_count: &HashMap<String, usize>,
+    hours: &[NaiveDateTime],
+    window: usize,
+    baseline: usize,
+    min_posts: usize,
+    threshold: f64,
+) -> Vec<TrendingHashtag> {
+    let (first, last) = match (hours.first(), hours.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Vec::new(),
+    };
+    let (window, baseline) = (window as i64, baseline as i64);
+    // Positions run over every hour from the first to the last, including empty ones
+    let series_len = (last - first).num_hours() + 1;
+    if window == 0 || baseline == 0 || series_len < baseline + window {
+        return Vec::new();
+    }
+    
+    // One index of the plausible hours, shared by the totals and every hashtag
+    let hour_index: HashMap<String, i64> = hours.iter().map(|&hour| (hour_key(hour), (hour - first).num_hours())).collect();
+    let totals = SparseCounts::new(hours.iter().map(|&hour| {
+        let key = hour_key(hour);
+        (hour_index[&key], hour_count[&key])
+    }).collect());
+    
+    let mut trending = Vec::new();
+    for (tag, tag_hours) in hashtag_hour_count {
+        if tag_hours.values().sum::<usize>() < min_posts {
+            continue;
+        }
+        
+        let counts = SparseCounts::new(
+            tag_hours
+                .iter()
+                .filter_map(|(hour, count)| hour_index.get(hour).map(|&position| (position, *count)))
+                .collect(),
+        );
+        // Windows that hold at least one post of the hashtag, in time order
+        let mut starts: Vec<i64> = counts.positions
+            .iter()
+            .flat_map(|&position| (position - window + 1).max(baseline)..=position.min(series_len - window))
+            .collect();
+        starts.sort_unstable();
+        starts.dedup();
+        
+        let mut best: Option<TrendingHashtag> = None;
+        for start in starts {
+            let observed = counts.sum(start, start + window);
+            if observed < min_posts {
+                continue;
+            }
+            
+            let baseline_posts = counts.sum(start - baseline, start) as f64;
+            let window_total = totals.sum(start, start + window) as f64;
+            let baseline_total = totals.sum(start - baseline, start) as f64;
+            let volume_ratio = if baseline_total > 0.0 {
+                window_total / baseline_total
+            } else {
+                window as f64 / baseline as f64
+            };
+            
+            // One pseudo-post keeps brand-new hashtags from having zero expectation
+            let expected = (baseline_posts + 1.0) * volume_ratio;
+            let score = (observed as f64 - expected) / expected.sqrt();
+            
+            if score >= threshold && best.as_ref().is_none_or(|b| score > b.score) {
+                best = Some(TrendingHashtag {
+                    tag: tag.clone(),
+                    window_start: hour_key(first + Duration::hours(start)),
+                    window_posts: observed,
+                    expected_posts: expected,
+                    score,
+                });
+            }
+        }
+        trending.extend(best);
+    }
+    
+    trending.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    trending
+}
+
+pub(crate) fn dump_trending_hashtags(trending: &[TrendingHashtag], window: usize, print_limit: usize, output_dir: &Path) {
+    let lines: Vec<String> = trending
+        .iter()
+        .enumerate()
+        .map(|(i, trend)| {
+            let window_range = match parse_hour_key(&trend.window_start) {
+                Some(start) => format!(
+                    "{} to {}",
+                    start.format("%Y-%m-%d %H:00"),
+                    (start + Duration::hours(window as i64)).format("%Y-%m-%d %H:00")
+                ),
+                None => trend.window_start.clone(),
+            };
+            format!(
+                "{}. #{} during {} with {} posts (expected {:.1}, burst score {:.2})",
+                i + 1, trend.tag, window_range, trend.window_posts, trend.expected_posts, trend.score
+            )
+        })
+        .collect();
+    
+    let mut printed: Vec<String> = lines.iter().take(print_limit).cloned().collect();
+    if trending.is_empty() {
+        printed.push("No trending hashtags found".to_string());
+    }
+    print_ranking("Trending Hashtags", &printed);
+    write_ranking("Trending Hashtags", "trending_hashtags.txt", &lines, output_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_positive_count, plausible_hours};
+    
+    #[test]
+    fn trending_hashtags_score_bursts_against_their_baseline() {
+        // Steady background traffic for two days, then #launch bursts in one hour
+        let mut hour_count = HashMap::new();
+        let mut hashtag_hour_count: HashMap<String, HashMap<String, usize>> = HashMap::new();
+        let start = parse_hour_key("2025-02-01 00").unwrap();
+        for offset in 0..48 {
+            let hour = hour_key(start + Duration::hours(offset));
+            hour_count.insert(hour.clone(), 10);
+            hashtag_hour_count.entry("steady".to_string()).or_default().insert(hour, 2);
+        }
+        hashtag_hour_count.entry("launch".to_string()).or_default().insert("2025-02-02 12".to_string(), 8);
+        // A bogus timestamp stays out of the shared hour index
+        hour_count.insert("1970-01-01 00".to_string(), 1);
+        hashtag_hour_count.entry("launch".to_string()).or_default().insert("1970-01-01 00".to_string(), 1);
+        
+        let (hours, dropped) = plausible_hours(&hour_count);
+        assert_eq!(dropped, 1);
+        let trending = detect_trending_hashtags(&hashtag_hour_count, &hour_count, &hours, 2, 12, 3, 3.0);
+        assert_eq!(trending.len(), 1);
+        assert_eq!(trending[0].tag, "launch");
+        assert_eq!(trending[0].window_posts, 8);
+        assert!(trending[0].score.is_finite() && trending[0].score > 3.0);
+        
+        assert!(detect_trending_hashtags(&hashtag_hour_count, &hour_count, &hours, 24, 48, 3, 3.0).is_empty());
+        assert!(parse_positive_count("0").is_err());
+        assert_eq!(parse_positive_count("5"), Ok(5));
+    }
+}
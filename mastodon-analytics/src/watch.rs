@@ -0,0 +1,275 @@
+//! Live ingestion of growing or rotating input (the `watch` subcommand)
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::{happy_score, hour_ranking_lines, preprocess_data, processing_data, sad_score, top_n_by_value, top_n_users, user_ranking_lines, write_ranking, Aggregates, PartitionedData, ProcessingOptions};
+use crate::engagement::dump_hour_engagement;
+
+// Device and inode of a file, which stay the same when a rotation renames it
+type FileIdentity = (u64, u64);
+
+// Read position in a tailed file; another file at its path or a shorter file means it was
+// replaced or truncated
+#[derive(Debug, Default)]
+struct TailedFile {
+    identity: FileIdentity,
+    offset: u64,
+}
+
+// Settings of the `watch` subcommand
+#[derive(Debug, Clone)]
+pub(crate) struct WatchOptions {
+    // Seconds between rewrites of the result files
+    pub(crate) interval: f64,
+    // Seconds between checks for new data
+    pub(crate) poll: f64,
+    // Stop after this many seconds without new data
+    pub(crate) max_idle: Option<f64>,
+}
+
+// A file to read on this poll; `renamed` marks a tailed file that a rotation moved out of the
+// watched set (live.ndjson to live.ndjson.1, say), which is read until it is drained
+struct WatchedFile {
+    path: PathBuf,
+    renamed: bool,
+}
+
+// The file itself, or the .ndjson files of a directory, plus the files next to them that are
+// still being tailed under another name, from oldest to newest
+fn watched_files(path: &Path, tailed: &HashMap<FileIdentity, TailedFile>) -> Vec<WatchedFile> {
+    let (dir, is_dir) = if path.is_dir() {
+        (path, true)
+    } else {
+        (path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new(".")), false)
+    };
+    let watched = |file: &Path| {
+        if is_dir {
+            file.extension().is_some_and(|extension| extension == "ndjson")
+        } else {
+            file == path
+        }
+    };
+    let mut files: Vec<(i64, WatchedFile)> = fs::read_dir(dir)
+        .map(|entries| {
+            entries.filter_map(|entry| {
+                let file = entry.ok()?.path();
+                let metadata = fs::metadata(&file).ok()?;
+                let renamed = !watched(&file);
+                if renamed && !tailed.contains_key(&(metadata.dev(), metadata.ino())) {
+                    return None;
+                }
+                Some((metadata.mtime(), WatchedFile { path: file, renamed }))
+            })
+            .collect()
+        })
+        .unwrap_or_default();
+    files.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.path.cmp(&b.1.path)));
+    files.into_iter().map(|(_, file)| file).collect()
+}
+
+// Call `handle` with every complete line appended to `path` since the last call. A partial
+// last line is left for the next call, when its writer will have finished it.
+fn read_appended_lines<F: FnMut(&str)>(path: &Path, tailed: &mut TailedFile, mut handle: F) -> usize {
+    // The metadata of the opened file, so that a replacement in between cannot mix up the two
+    let Ok(mut file) = File::open(path) else {
+        return 0;
+    };
+    let Ok(metadata) = file.metadata() else {
+        return 0;
+    };
+    let identity = (metadata.dev(), metadata.ino());
+    if identity != tailed.identity || metadata.size() < tailed.offset {
+        *tailed = TailedFile { identity, offset: 0 };
+    }
+    if io::Seek::seek(&mut file, io::SeekFrom::Start(tailed.offset)).is_err() {
+        return 0;
+    }
+    
+    let mut reader = io::BufReader::new(io::Read::take(file, metadata.size() - tailed.offset));
+    let mut line = Vec::new();
+    let mut lines = 0;
+    loop {
+        line.clear();
+        match io::BufRead::read_until(&mut reader, b'\n', &mut line) {
+            Ok(read) if read > 0 && line.ends_with(b"\n") => {
+                tailed.offset += read as u64;
+                if let Some(pre_line) = std::str::from_utf8(&line).ok().and_then(preprocess_data) {
+                    handle(&pre_line);
+                    lines += 1;
+                }
+            }
+            _ => break,
+        }
+    }
+    lines
+}
+
+// Rewrite the hour and user rankings, without printing them. They are written to a staging
+// directory first and then moved into place, so readers never see a half-written file.
+fn dump_watch_results(aggregates: &Aggregates, top_n: usize, output_dir: &Path) {
+    let staging_dir = output_dir.join(".watch-staging");
+    let hours = &aggregates.hour_sentiment;
+    let users = &aggregates.user_sentiment;
+    let happiest_hours = hour_ranking_lines(&top_n_by_value(hours, top_n, true), happy_score);
+    write_ranking("Top Happiest Hours", "happiest_hours.txt", &happiest_hours, &staging_dir);
+    let saddest_hours = hour_ranking_lines(&top_n_by_value(hours, top_n, false), sad_score);
+    write_ranking("Top Saddest Hours", "saddest_hours.txt", &saddest_hours, &staging_dir);
+    let happiest_users = user_ranking_lines(&top_n_users(users, top_n, true), happy_score);
+    write_ranking("Top Happiest Users", "happiest_users.txt", &happiest_users, &staging_dir);
+    let saddest_users = user_ranking_lines(&top_n_users(users, top_n, false), sad_score);
+    write_ranking("Top Saddest Users", "saddest_users.txt", &saddest_users, &staging_dir);
+    dump_hour_engagement(&aggregates.hour_engagement, &aggregates.hour_sentiment, &aggregates.hour_count, &staging_dir);
+    
+    for entry in fs::read_dir(&staging_dir).expect("Failed to read staging directory").flatten() {
+        fs::rename(entry.path(), output_dir.join(entry.file_name())).expect("Failed to move result file into place");
+    }
+}
+
+// Follow `path` (a file, or a directory of rotating .ndjson files), adding every new status to
+// the aggregates and rewriting the results at most every `interval` seconds while data arrives
+pub(crate) fn run_watch(path: &Path, output_dir: &Path, watch: &WatchOptions, options: &ProcessingOptions, top_n: usize) {
+    let mut tailed: HashMap<FileIdentity, TailedFile> = HashMap::new();
+    let mut aggregates = Aggregates::default();
+    let mut partitioned = PartitionedData::default();
+    let mut total_lines = 0;
+    let mut pending_lines = 0;
+    let mut last_dump = Instant::now();
+    let mut last_data = Instant::now();
+    
+    println!("Watching {} (results every {} seconds)", path.display(), watch.interval);
+    loop {
+        // Positions follow files by identity, so a file renamed by a rotation is neither read
+        // again from the start nor dropped before the lines written to it before the rename
+        let mut still_watched = HashMap::new();
+        let mut new_lines = 0;
+        for file in watched_files(path, &tailed) {
+            let Ok(metadata) = fs::metadata(&file.path) else {
+                continue;
+            };
+            let mut position = tailed.remove(&(metadata.dev(), metadata.ino())).unwrap_or_default();
+            let lines = read_appended_lines(&file.path, &mut position, |line| {
+                processing_data(line, &mut aggregates, &mut partitioned, options);
+            });
+            new_lines += lines;
+            // A renamed file is drained once a poll finds nothing new and no unfinished line
+            if !file.renamed || lines > 0 || position.offset < metadata.size() {
+                still_watched.insert(position.identity, position);
+            }
+        }
+        tailed = still_watched;
+        if new_lines > 0 {
+            pending_lines += new_lines;
+            last_data = Instant::now();
+        }
+        
+        let idle = watch.max_idle.is_some_and(|max_idle| last_data.elapsed().as_secs_f64() >= max_idle);
+        if pending_lines > 0 && (idle || last_dump.elapsed().as_secs_f64() >= watch.interval) {
+            dump_watch_results(&aggregates, top_n, output_dir);
+            total_lines += pending_lines;
+            println!("Ingested {} new lines ({} in total)", pending_lines, total_lines);
+            pending_lines = 0;
+            last_dump = Instant::now();
+        }
+        if idle {
+            println!("No new data for {} seconds, stopping", watch.max_idle.unwrap_or_default());
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_secs_f64(watch.poll));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::tests::scratch_dir;
+    
+    fn read(path: &Path, tailed: &mut TailedFile) -> Vec<String> {
+        let mut lines = Vec::new();
+        read_appended_lines(path, tailed, |line| lines.push(line.to_string()));
+        lines
+    }
+    
+    fn append(path: &Path, text: &str) {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+    
+    #[test]
+    fn partial_lines_wait_for_their_newline() {
+        let dir = scratch_dir("tail-partial");
+        let path = dir.join("live.ndjson");
+        let mut tailed = TailedFile::default();
+        append(&path, "{\"n\":1}\n{\"n\":");
+        assert_eq!(read(&path, &mut tailed), ["{\"n\":1}"]);
+        assert!(read(&path, &mut tailed).is_empty());
+        append(&path, "2}\n");
+        assert_eq!(read(&path, &mut tailed), ["{\"n\":2}"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn truncated_files_are_read_from_the_start() {
+        let dir = scratch_dir("tail-truncate");
+        let path = dir.join("live.ndjson");
+        let mut tailed = TailedFile::default();
+        append(&path, "{\"n\":1}\n{\"n\":2}\n");
+        assert_eq!(read(&path, &mut tailed).len(), 2);
+        
+        // Truncated in place: same inode, fewer bytes than were read
+        fs::OpenOptions::new().write(true).truncate(true).open(&path).unwrap();
+        append(&path, "{\"n\":3}\n");
+        assert_eq!(read(&path, &mut tailed), ["{\"n\":3}"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn replaced_files_are_read_from_the_start_and_renamed_ones_continue() {
+        let dir = scratch_dir("tail-rotate");
+        let path = dir.join("live.ndjson");
+        let mut tailed = TailedFile::default();
+        append(&path, "{\"n\":1}\n");
+        assert_eq!(read(&path, &mut tailed).len(), 1);
+        
+        // A rotation moves the file away and writes a new one at its path, longer than the old
+        let rotated = dir.join("rotated.ndjson");
+        fs::rename(&path, &rotated).unwrap();
+        append(&rotated, "{\"n\":2}\n");
+        append(&path, "{\"n\":3,\"longer\":true}\n");
+        let mut fresh = TailedFile::default();
+        assert_eq!(read(&path, &mut fresh), ["{\"n\":3,\"longer\":true}"]);
+        
+        // The old position still belongs to the renamed file, and other files reset it
+        assert_eq!(read(&rotated, &mut tailed), ["{\"n\":2}"]);
+        assert_eq!(read(&path, &mut tailed), ["{\"n\":3,\"longer\":true}"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn tailed_files_are_followed_out_of_the_watched_set() {
+        let dir = scratch_dir("watch-rename");
+        let path = dir.join("live.ndjson");
+        append(&path, "{\"n\":1}\n");
+        append(&dir.join("notes.txt"), "not a status\n");
+        let names = |files: Vec<WatchedFile>| {
+            files.into_iter()
+                .map(|file| (file.path.file_name().unwrap().to_string_lossy().into_owned(), file.renamed))
+                .collect::<Vec<_>>()
+        };
+        let mut tailed = HashMap::new();
+        assert_eq!(names(watched_files(&dir, &tailed)), [("live.ndjson".to_string(), false)]);
+        
+        let mut position = TailedFile::default();
+        read(&path, &mut position);
+        tailed.insert(position.identity, position);
+        fs::rename(&path, dir.join("live.ndjson.1")).unwrap();
+        assert_eq!(names(watched_files(&dir, &tailed)), [("live.ndjson.1".to_string(), true)]);
+        // Watching the file itself follows it the same way
+        assert_eq!(names(watched_files(&path, &tailed)), [("live.ndjson.1".to_string(), true)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
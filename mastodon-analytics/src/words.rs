@@ -0,0 +1,220 @@
+//! Word and bigram counts and their association with sentiment
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use crate::{csv_field, print_ranking, write_csv, write_ranking, Aggregates, TermCounts, UNKNOWN_LANGUAGE};
+
+// Bundled stopword lists, keyed by the language codes Mastodon reports
+const STOPWORDS: &[(&str, &str)] = &[
+    ("en", "a about above after again against all am an and any are as at be because been before being below \
+            between both but by can could did do does doing down during each few for from further had has have \
+            having he her here hers herself him himself his how i i'm if in into is it it's its itself just let \
+            me more most my myself no nor not now of off on once only or other our ours ourselves out over own \
+            same she should so some such than that that's the their theirs them themselves then there these they \
+            this those through to too under until up very was we were what when where which while who whom why \
+            will with would you you're your yours yourself yourselves also get got one like"),
+    ("de", "aber alle allem allen aller alles als also am an ander andere anderen auch auf aus bei bin bis bist da \
+            damit dann das dass dein deine dem den denn der des dich die dies diese diesem diesen dieser dieses dir \
+            doch dort du durch ein eine einem einen einer eines er es etwas euch euer für hab habe haben hat hatte \
+            ich ihr ihre im in ist ja jede jedem jeden jeder jedes jetzt kann kein keine man mein meine mich mir mit \
+            muss nach nicht nichts noch nun nur ob oder ohne sehr sein seine sich sie sind so solche soll sondern \
+            um und uns unser unter viel vom von vor war waren was weil wenn wer wie wieder will wir wird wo zu zum \
+            zur über"),
+    ("fr", "au aux avec ce ces c'est dans de des du elle en et eux il ils je la le les leur lui ma mais me même \
+            mes moi mon ne nos notre nous on ou par pas pour qu que qui sa se ses son sur ta te tes toi ton tu un \
+            une vos votre vous est sont été être avoir ai as avons avez ont plus tout tous très y"),
+    ("es", "a al algo como con de del el ella ellas ellos en entre era es esa ese eso esta este esto fue ha hay la \
+            las le les lo los me mi muy más ni no nos o para pero por que qué se si sin sobre su sus también te \
+            tu un una uno y ya yo está están ser"),
+    ("nl", "aan al als bij dan dat de der deze die dit door een en er had heb hebben het hij hem hier hoe hun ik \
+            in is je kan me meer men met mij mijn naar niet nog nu of om ons ook op over te tot u uit van veel \
+            voor was wat we wel wij zal ze zich zij zijn zo zou"),
+    ("it", "a al alla anche che chi ci come con da dal dei del della di e è gli ha ho i il in io la le lei lo lui \
+            ma mi mio ne nel nella no noi non o per più quando questo se si sono su sua suo ti tra tu un una uno \
+            vi voi"),
+    ("pt", "a ao aos as com como da das de do dos e ela ele eles em entre era essa esse está eu foi há isso já \
+            mais mas me meu muito na nas não no nos o os ou para pela pelo por que se sem seu sua são também te \
+            tem um uma você"),
+    ("sv", "alla att av och de dem den denna det detta dig din du efter en ett från för han har hon honom hur i \
+            inte jag kan man med men mig min mot ni nu när och om oss på sig sin sitt som så till under upp ut \
+            var vara vi vid vad åt är"),
+];
+
+// Dirichlet prior strength for the log-odds ratio; the prior follows the overall term frequencies
+const TERM_PRIOR_STRENGTH: f64 = 1000.0;
+
+pub(crate) fn bundled_stopwords() -> HashMap<String, HashSet<String>> {
+    let mut stopwords: HashMap<String, HashSet<String>> = STOPWORDS
+        .iter()
+        .map(|(language, words)| (language.to_string(), words.split_whitespace().map(String::from).collect()))
+        .collect();
+    // Statuses without a language are most often English
+    if let Some(english) = stopwords.get("en").cloned() {
+        stopwords.insert(UNKNOWN_LANGUAGE.to_string(), english);
+    }
+    stopwords
+}
+
+// Lower-cased words of extracted text; mentions, hashtags, links and numbers are skipped
+fn tokenize_words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter(|chunk| !(chunk.starts_with('@') || chunk.starts_with('#') || chunk.contains('/')))
+        .flat_map(|chunk| chunk.split(|c: char| !(c.is_alphanumeric() || c == '\'')))
+        .map(|token| token.trim_matches('\'').to_lowercase())
+        .filter(|token| token.chars().count() > 1 && !token.chars().all(char::is_numeric))
+        .collect()
+}
+
+// Count the words and bigrams of one status. Stopwords are dropped and break bigrams.
+pub(crate) fn count_terms(text: &str, stopwords: Option<&HashSet<String>>, sentiment: f64, aggregates: &mut Aggregates) {
+    let mut previous: Option<String> = None;
+    for word in tokenize_words(text) {
+        if stopwords.is_some_and(|stopwords| stopwords.contains(&word)) {
+            previous = None;
+            continue;
+        }
+        if let Some(previous) = previous.take() {
+            aggregates.bigram_counts.entry(format!("{} {}", previous, word)).or_default().add(sentiment);
+        }
+        aggregates.word_counts.entry(word.clone()).or_default().add(sentiment);
+        previous = Some(word);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TermAssociation {
+    term: String,
+    counts: TermCounts,
+    // Log-odds of appearing in positive rather than negative statuses, and its z-score
+    log_odds: f64,
+    z_score: f64,
+}
+
+// Log-odds ratio with an informative Dirichlet prior (Monroe, Colaresi & Quinn, 2008) between
+// occurrences in positive and negative statuses, for terms seen at least `min_count` times
+pub(crate) fn term_associations(terms: &HashMap<String, TermCounts>, min_count: usize) -> Vec<TermAssociation> {
+    let total: f64 = terms.values().map(|counts| counts.count as f64).sum();
+    let positive_total: f64 = terms.values().map(|counts| counts.positive as f64).sum();
+    let negative_total: f64 = terms.values().map(|counts| counts.negative as f64).sum();
+    if total == 0.0 {
+        return Vec::new();
+    }
+    
+    let mut associations: Vec<TermAssociation> = terms
+        .iter()
+        .filter(|(_, counts)| counts.count >= min_count)
+        .map(|(term, counts)| {
+            let prior = TERM_PRIOR_STRENGTH * counts.count as f64 / total;
+            let positive = counts.positive as f64 + prior;
+            let negative = counts.negative as f64 + prior;
+            let log_odds = (positive / (positive_total + TERM_PRIOR_STRENGTH - positive)).ln()
+                - (negative / (negative_total + TERM_PRIOR_STRENGTH - negative)).ln();
+            let variance = 1.0 / positive + 1.0 / negative;
+            TermAssociation {
+                term: term.clone(),
+                counts: *counts,
+                log_odds,
+                z_score: log_odds / variance.sqrt(),
+            }
+        })
+        .collect();
+    associations.sort_by(|a, b| b.counts.count.cmp(&a.counts.count).then_with(|| a.term.cmp(&b.term)));
+    associations
+}
+
+// The `n` terms leaning furthest towards positive (or negative) sentiment
+pub(crate) fn top_n_associations(associations: &[TermAssociation], n: usize, positive: bool) -> Vec<&TermAssociation> {
+    let mut ranked: Vec<&TermAssociation> = associations
+        .iter()
+        .filter(|association| if positive { association.z_score > 0.0 } else { association.z_score < 0.0 })
+        .collect();
+    ranked.sort_by(|a, b| {
+        let ordering = a.z_score.partial_cmp(&b.z_score).unwrap_or(Ordering::Equal);
+        if positive { ordering.reverse() } else { ordering }
+    });
+    ranked.truncate(n);
+    ranked
+}
+
+pub(crate) fn dump_term_ranking(title: &str, file_name: &str, ranking: &[&TermAssociation], output_dir: &Path) {
+    let lines: Vec<String> = ranking
+        .iter()
+        .enumerate()
+        .map(|(i, association)| {
+            format!(
+                "{}. \"{}\" with z-score {:+.3} ({} uses, {} positive, {} negative, mean sentiment {:+.4})",
+                i + 1,
+                association.term,
+                association.z_score,
+                association.counts.count,
+                association.counts.positive,
+                association.counts.negative,
+                association.counts.mean_sentiment()
+            )
+        })
+        .collect();
+    print_ranking(title, &lines);
+    write_ranking(title, file_name, &lines, output_dir);
+}
+
+pub(crate) fn dump_term_table(file_name: &str, key_header: &str, associations: &[TermAssociation], output_dir: &Path) {
+    let rows = associations.iter().map(|association| {
+        format!(
+            "{},{},{},{},{},{},{}",
+            csv_field(&association.term),
+            association.counts.count,
+            association.counts.positive,
+            association.counts.negative,
+            association.counts.mean_sentiment(),
+            association.log_odds,
+            association.z_score
+        )
+    });
+    let header = format!("{},count,positive,negative,mean_sentiment,log_odds,z_score", key_header);
+    write_csv(file_name, &header, rows, output_dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{aggregate, exported_status};
+    use crate::{LexiconScorer, ProcessingOptions, SentimentSource};
+    use serde_json::json;
+    use std::sync::Arc;
+    
+    #[test]
+    fn words_and_lexicon_share_one_text_extraction() {
+        let options = ProcessingOptions {
+            scorer: Some(Arc::new(LexiconScorer::bundled())),
+            sentiment_source: SentimentSource::Both,
+            count_words: true,
+            stopwords: bundled_stopwords(),
+            ..ProcessingOptions::default()
+        };
+        let lines = [
+            exported_status(json!({"content": "<p>Great coffee &amp; cake</p>", "sentiment": 0.8})),
+            exported_status(json!({"content": "<p>Great coffee, great :blobcat:</p>", "sentiment": 0.6,
+                "emojis": [{"shortcode": "blobcat"}]})),
+            exported_status(json!({"content": "<p>Terrible coffee, the <b>queue</b></p>", "sentiment": -0.7})),
+            exported_status(json!({"content": "<p>Terrible weather</p>", "sentiment": null})),
+        ];
+        let (aggregates, _) = aggregate(&lines, &options);
+        
+        // The lexicon scores the status without a field, so all four are counted
+        assert_eq!(aggregates.word_counts["coffee"].count, 3);
+        assert_eq!(aggregates.word_counts["great"].count, 3);
+        assert_eq!(aggregates.word_counts["terrible"].negative, 2);
+        assert!(!aggregates.word_counts.contains_key("the"));
+        assert!(!aggregates.word_counts.contains_key("blobcat"));
+        assert!(!aggregates.word_counts.contains_key("amp"));
+        assert_eq!(aggregates.bigram_counts["great coffee"].count, 2);
+        assert!(!aggregates.bigram_counts.contains_key("coffee queue"));
+        
+        let associations = term_associations(&aggregates.word_counts, 2);
+        let log_odds = |term: &str| associations.iter().find(|a| a.term == term).unwrap().log_odds;
+        assert!(log_odds("great") > 0.0);
+        assert!(log_odds("terrible") < 0.0);
+        assert!(log_odds("great") > log_odds("coffee") && log_odds("coffee") > log_odds("terrible"));
+    }
+}